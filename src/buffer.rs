@@ -0,0 +1,21 @@
+use crate::document::Document;
+use crate::position::Position;
+
+#[derive(Default)]
+pub struct Buffer {
+    pub document: Document,
+    // Where the cursor and scroll offset were when the buffer was last shown.
+    pub cursor_position: Position,
+    pub offset: Position,
+    // A change on disk has been pointed out while the buffer was in the background.
+    pub disk_change_noticed: bool,
+}
+
+impl Buffer {
+    pub fn name(&self) -> String {
+        self.document
+            .file_name
+            .clone()
+            .unwrap_or_else(|| "[No Name]".to_string())
+    }
+}
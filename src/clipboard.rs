@@ -0,0 +1,78 @@
+use std::collections::{HashMap, VecDeque};
+use std::io::{stdout, Write};
+
+const KILL_RING_SIZE: usize = 16;
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+// Copied text, kept with '\n' line breaks whatever the document it came from uses.
+#[derive(Clone)]
+pub struct Yank {
+    pub text: String,
+    // Whole lines copied without a selection go back in as whole lines.
+    pub linewise: bool,
+}
+
+#[derive(Default)]
+pub struct Clipboard {
+    ring: VecDeque<Yank>,
+    registers: HashMap<char, Yank>,
+    // Also hand copies to the terminal's clipboard with OSC 52, which works over SSH.
+    pub osc52: bool,
+}
+
+impl Clipboard {
+    // Copies into the kill ring, or only into the named register when one is given.
+    pub fn yank(&mut self, register: Option<char>, yank: Yank) {
+        if let Some(register) = register {
+            self.registers.insert(register, yank);
+            return;
+        }
+
+        if self.osc52 {
+            set_system_clipboard(&yank.text);
+        }
+
+        self.ring.push_front(yank);
+        self.ring.truncate(KILL_RING_SIZE);
+    }
+
+    // The register's contents, or the `index`th most recent yank in the kill ring.
+    pub fn get(&self, register: Option<char>, index: usize) -> Option<&Yank> {
+        match register {
+            Some(register) => self.registers.get(&register),
+            None => self.ring.get(index),
+        }
+    }
+
+    pub fn ring_len(&self) -> usize {
+        self.ring.len()
+    }
+}
+
+// The terminal, not the editor, owns the system clipboard: ask it to take the text.
+fn set_system_clipboard(text: &str) {
+    let mut out = stdout();
+    write!(out, "\x1b]52;c;{}\x07", base64(text.as_bytes())).ok();
+    out.flush().ok();
+}
+
+fn base64(bytes: &[u8]) -> String {
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+
+    for chunk in bytes.chunks(3) {
+        let triple = chunk
+            .iter()
+            .enumerate()
+            .fold(0_u32, |triple, (index, byte)| triple | u32::from(*byte) << (16 - index * 8));
+
+        for index in 0..4 {
+            if index <= chunk.len() {
+                encoded.push(char::from(BASE64[(triple >> (18 - index * 6)) as usize & 0x3f]));
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+
+    encoded
+}
@@ -0,0 +1,334 @@
+use std::fs;
+use std::path::Path;
+
+use crate::editor::LineNumbers;
+use crate::keymap::{self, Command};
+
+use regex::Regex;
+
+// Ways of finishing a `set` argument, for tab completion.
+const SETTINGS: &[&str] = &["tabwidth=", "softtabs", "nosofttabs", "wrap", "nowrap", "mouse", "nomouse", "linenumbers="];
+
+// A line typed at the command line, ready to run.
+pub enum ExCommand {
+    // Saves, or writes a copy under another name as vi does.
+    Write(Option<String>),
+    // Saves under a new name, which the buffer then goes by.
+    SaveAs(String),
+    Quit { force: bool },
+    WriteQuit,
+    Edit(String),
+    Goto(usize),
+    Set(Setting),
+    Substitute(Substitution),
+    // Any command keys can be bound to, by its keys.toml name.
+    Run(Command),
+}
+
+pub enum Setting {
+    TabWidth(usize),
+    SoftTabs(bool),
+    SoftWrap(bool),
+    Mouse(bool),
+    LineNumbers(LineNumbers),
+}
+
+pub struct Substitution {
+    pub regex: Regex,
+    pub replacement: String,
+    // Every match on a line rather than only the first.
+    pub global: bool,
+    // %s works through the whole document, s only on the cursor's line.
+    pub whole_document: bool,
+}
+
+// What follows a command's name, which decides what Tab completes there.
+#[derive(Clone, Copy, PartialEq)]
+enum Argument {
+    Nothing,
+    Number,
+    File,
+    Setting,
+}
+
+struct Definition {
+    // The full name first, then its abbreviations.
+    names: &'static [&'static str],
+    argument: Argument,
+    parse: fn(&str) -> Result<ExCommand, String>,
+}
+
+const DEFINITIONS: &[Definition] = &[
+    Definition { names: &["write", "w"], argument: Argument::File, parse: write },
+    Definition { names: &["saveas", "sav"], argument: Argument::File, parse: save_as },
+    Definition { names: &["quit", "q"], argument: Argument::Nothing, parse: |_| Ok(ExCommand::Quit { force: false }) },
+    Definition { names: &["quit!", "q!"], argument: Argument::Nothing, parse: |_| Ok(ExCommand::Quit { force: true }) },
+    Definition { names: &["wq", "x"], argument: Argument::Nothing, parse: |_| Ok(ExCommand::WriteQuit) },
+    Definition { names: &["edit", "e"], argument: Argument::File, parse: edit },
+    Definition { names: &["goto"], argument: Argument::Number, parse: goto },
+    Definition { names: &["set"], argument: Argument::Setting, parse: set },
+];
+
+// Commands from the table above come before the keymap's commands of the same name.
+pub fn parse(line: &str) -> Result<ExCommand, String> {
+    let line = line.trim();
+
+    // A bare number jumps to that line.
+    if line.chars().all(|c| c.is_ascii_digit()) {
+        return goto(line);
+    }
+    if let Some(rest) = line.strip_prefix("%s").filter(|rest| is_delimiter(rest)) {
+        return substitute(rest, true);
+    }
+    if let Some(rest) = line.strip_prefix('s').filter(|rest| is_delimiter(rest)) {
+        return substitute(rest, false);
+    }
+
+    let (name, argument) = line
+        .split_once(char::is_whitespace)
+        .map_or((line, ""), |(name, argument)| (name, argument.trim()));
+
+    if let Some(definition) = DEFINITIONS.iter().find(|definition| definition.names.contains(&name)) {
+        if definition.argument == Argument::Nothing && !argument.is_empty() {
+            return Err(format!("{} takes no argument", name));
+        }
+        return (definition.parse)(argument);
+    }
+
+    match keymap::command_named(name) {
+        Some(command) if argument.is_empty() => Ok(ExCommand::Run(command)),
+        Some(_) => Err(format!("{} takes no argument", name)),
+        None => Err(format!("Not a command: {}", name)),
+    }
+}
+
+// Ways of finishing the word being typed, each given as the whole new line: command names
+// in the first word, then file names or settings for the commands that take them.
+pub fn completions(line: &str) -> Vec<String> {
+    let Some((name, argument)) = line.split_once(' ') else {
+        let mut names: Vec<String> = DEFINITIONS
+            .iter()
+            .map(|definition| definition.names[0])
+            .chain(keymap::command_names())
+            .filter(|name| name.starts_with(line))
+            .map(String::from)
+            .collect();
+        names.sort();
+        names.dedup();
+        return names;
+    };
+
+    let argument = argument.trim_start();
+    let options = match DEFINITIONS.iter().find(|definition| definition.names.contains(&name)).map(|definition| definition.argument) {
+        Some(Argument::File) => files(argument),
+        Some(Argument::Setting) => SETTINGS
+            .iter()
+            .filter(|setting| setting.starts_with(argument))
+            .map(|setting| setting.to_string())
+            .collect(),
+        _ => Vec::new(),
+    };
+
+    options.into_iter().map(|option| format!("{} {}", name, option)).collect()
+}
+
+fn write(argument: &str) -> Result<ExCommand, String> {
+    Ok(ExCommand::Write(Some(argument.to_string()).filter(|file_name| !file_name.is_empty())))
+}
+
+fn save_as(argument: &str) -> Result<ExCommand, String> {
+    if argument.is_empty() {
+        return Err("saveas needs a file name".to_string());
+    }
+    Ok(ExCommand::SaveAs(argument.to_string()))
+}
+
+fn edit(argument: &str) -> Result<ExCommand, String> {
+    if argument.is_empty() {
+        return Err("edit needs a file name".to_string());
+    }
+    Ok(ExCommand::Edit(argument.to_string()))
+}
+
+fn goto(argument: &str) -> Result<ExCommand, String> {
+    match argument.parse::<usize>() {
+        Ok(line) if line > 0 => Ok(ExCommand::Goto(line)),
+        _ => Err("goto needs a line number".to_string()),
+    }
+}
+
+fn set(argument: &str) -> Result<ExCommand, String> {
+    let setting = match argument.split_once('=') {
+        Some(("tabwidth", width)) => match width.parse::<usize>() {
+            Ok(width) if width > 0 => Setting::TabWidth(width),
+            _ => return Err("tabwidth must be a number above zero".to_string()),
+        },
+        Some(("linenumbers", "off")) => Setting::LineNumbers(LineNumbers::Off),
+        Some(("linenumbers", "absolute")) => Setting::LineNumbers(LineNumbers::Absolute),
+        Some(("linenumbers", "relative")) => Setting::LineNumbers(LineNumbers::Relative),
+        Some(("linenumbers", _)) => return Err("linenumbers must be off, absolute or relative".to_string()),
+        Some(_) => return Err(format!("Unknown setting: {}", argument)),
+        None => match argument {
+            "softtabs" | "nosofttabs" => Setting::SoftTabs(argument == "softtabs"),
+            "wrap" | "nowrap" => Setting::SoftWrap(argument == "wrap"),
+            "mouse" | "nomouse" => Setting::Mouse(argument == "mouse"),
+            "" => return Err("set needs a setting".to_string()),
+            _ => return Err(format!("Unknown setting: {}", argument)),
+        },
+    };
+    Ok(ExCommand::Set(setting))
+}
+
+// Any punctuation can separate the parts of s/pattern/replacement/flags.
+fn is_delimiter(rest: &str) -> bool {
+    rest.chars().next().is_some_and(|c| c.is_ascii_punctuation() && c != '\\')
+}
+
+fn substitute(text: &str, whole_document: bool) -> Result<ExCommand, String> {
+    let mut chars = text.chars();
+    let delimiter = chars.next().unwrap_or('/');
+    let parts = split_unescaped(chars.as_str(), delimiter);
+
+    let (pattern, replacement, flags) = match parts.as_slice() {
+        [pattern] => (pattern, "", ""),
+        [pattern, replacement] => (pattern, replacement.as_str(), ""),
+        [pattern, replacement, flags] => (pattern, replacement.as_str(), flags.as_str()),
+        _ => return Err(format!("Too many '{}' in substitution", delimiter)),
+    };
+
+    let mut global = false;
+    for flag in flags.chars() {
+        match flag {
+            'g' => global = true,
+            flag => return Err(format!("Unknown substitution flag: {}", flag)),
+        }
+    }
+
+    let regex = Regex::new(pattern).map_err(|error| format!("Invalid pattern: {}", error))?;
+    Ok(ExCommand::Substitute(Substitution {
+        regex,
+        replacement: replacement.to_string(),
+        global,
+        whole_document,
+    }))
+}
+
+// Splits `text` at each `delimiter` that a backslash does not escape. Other escapes are
+// left for the regex.
+fn split_unescaped(text: &str, delimiter: char) -> Vec<String> {
+    let mut parts = vec![String::new()];
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        let part = parts.last_mut().expect("parts is never empty");
+        match c {
+            '\\' if chars.peek() == Some(&delimiter) => part.extend(chars.next()),
+            '\\' => {
+                part.push(c);
+                part.extend(chars.next());
+            },
+            c if c == delimiter => parts.push(String::new()),
+            c => part.push(c),
+        }
+    }
+    parts
+}
+
+// Entries of the directory named so far in `prefix` whose names carry on from it, with a '/'
+// after directories. Hidden files only show up once a '.' has been typed.
+fn files(prefix: &str) -> Vec<String> {
+    let (directory, start) = match prefix.rfind('/') {
+        Some(index) => prefix.split_at(index + 1),
+        None => ("", prefix),
+    };
+    let Ok(entries) = fs::read_dir(if directory.is_empty() { Path::new(".") } else { Path::new(directory) }) else {
+        return Vec::new();
+    };
+
+    let mut files: Vec<String> = entries
+        .filter_map(Result::ok)
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            if !name.starts_with(start) || (name.starts_with('.') && !start.starts_with('.')) {
+                return None;
+            }
+            let slash = if entry.file_type().is_ok_and(|file_type| file_type.is_dir()) { "/" } else { "" };
+            Some(format!("{}{}{}", directory, name, slash))
+        })
+        .collect();
+    files.sort();
+    files
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(line: &str) -> String {
+        match parse(line) {
+            Ok(_) => panic!("{} parsed", line),
+            Err(error) => error,
+        }
+    }
+
+    #[test]
+    fn commands_parse_by_name_and_abbreviation() {
+        assert!(matches!(parse("w"), Ok(ExCommand::Write(None))));
+        assert!(matches!(parse("write  copy.txt "), Ok(ExCommand::Write(Some(name))) if name == "copy.txt"));
+        assert!(matches!(parse("sav b.txt"), Ok(ExCommand::SaveAs(name)) if name == "b.txt"));
+        assert!(matches!(parse("q!"), Ok(ExCommand::Quit { force: true })));
+        assert!(matches!(parse(" x "), Ok(ExCommand::WriteQuit)));
+        assert!(matches!(parse("42"), Ok(ExCommand::Goto(42))));
+        assert!(matches!(parse("set nowrap"), Ok(ExCommand::Set(Setting::SoftWrap(false)))));
+        assert!(matches!(parse("set tabwidth=2"), Ok(ExCommand::Set(Setting::TabWidth(2)))));
+        assert!(matches!(parse("undo"), Ok(ExCommand::Run(Command::Undo))));
+    }
+
+    #[test]
+    fn bad_lines_say_what_is_wrong() {
+        assert_eq!(error("frobnicate"), "Not a command: frobnicate");
+        assert_eq!(error("q now"), "q takes no argument");
+        assert_eq!(error("undo twice"), "undo takes no argument");
+        assert_eq!(error("sav"), "saveas needs a file name");
+        assert_eq!(error("goto x"), "goto needs a line number");
+        assert_eq!(error("0"), "goto needs a line number");
+        assert_eq!(error("set tabwidth=0"), "tabwidth must be a number above zero");
+        assert_eq!(error("set colour=red"), "Unknown setting: colour=red");
+    }
+
+    #[test]
+    fn substitutions_take_any_delimiter_and_flags() {
+        let Ok(ExCommand::Substitute(substitution)) = parse("%s#a/b#c#g") else {
+            panic!("not a substitution");
+        };
+        assert_eq!(substitution.regex.as_str(), "a/b");
+        assert_eq!(substitution.replacement, "c");
+        assert!(substitution.global && substitution.whole_document);
+
+        let Ok(ExCommand::Substitute(substitution)) = parse("s/x") else {
+            panic!("not a substitution");
+        };
+        assert_eq!(substitution.replacement, "");
+        assert!(!substitution.global && !substitution.whole_document);
+
+        assert_eq!(error("s/a/b/c/d"), "Too many '/' in substitution");
+        assert_eq!(error("s/a/b/i"), "Unknown substitution flag: i");
+        assert!(error("s/(/x/").starts_with("Invalid pattern"));
+    }
+
+    #[test]
+    fn split_unescaped_keeps_other_escapes() {
+        assert_eq!(split_unescaped("a/b/g", '/'), vec!["a", "b", "g"]);
+        assert_eq!(split_unescaped(r"a\/b/c", '/'), vec!["a/b", "c"]);
+        assert_eq!(split_unescaped(r"\d+/\\/", '/'), vec![r"\d+", r"\\", ""]);
+        assert_eq!(split_unescaped("", '/'), vec![""]);
+    }
+
+    #[test]
+    fn command_names_complete() {
+        assert_eq!(completions("sa"), vec!["save", "saveas"]);
+        assert!(completions("w").contains(&"write".to_string()));
+        assert_eq!(completions("set no"), vec!["set nosofttabs", "set nowrap", "set nomouse"]);
+        assert!(completions("goto 1").is_empty());
+    }
+}
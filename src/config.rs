@@ -0,0 +1,373 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::document::Document;
+use crate::editor::LineNumbers;
+
+use crossterm::style::Color;
+use toml::{Table, Value};
+
+pub const DEFAULT_TAB_WIDTH: usize = 4;
+
+#[derive(Clone, Copy)]
+pub struct Colors {
+    pub status_bg: Color,
+    pub status_fg: Color,
+    pub inactive_status_bg: Color,
+    pub inactive_status_fg: Color,
+    pub line_number: Color,
+    pub current_line_number: Color,
+}
+
+impl Default for Colors {
+    fn default() -> Self {
+        Self {
+            status_bg: Color::Rgb { r: 239, g: 239, b: 239 },
+            status_fg: Color::Rgb { r: 63, g: 63, b: 63 },
+            inactive_status_bg: Color::Rgb { r: 120, g: 120, b: 120 },
+            inactive_status_fg: Color::Rgb { r: 220, g: 220, b: 220 },
+            line_number: Color::Rgb { r: 110, g: 110, b: 110 },
+            current_line_number: Color::Rgb { r: 239, g: 239, b: 239 },
+        }
+    }
+}
+
+// Settings a [filetype.<name>] table can change for buffers of that type.
+#[derive(Default, Clone, Copy)]
+struct FileTypeSettings {
+    tab_width: Option<usize>,
+    soft_tabs: Option<bool>,
+}
+
+pub struct Config {
+    pub tab_width: usize,
+    // Tab inserts spaces up to the next tab stop.
+    pub soft_tabs: bool,
+    pub line_numbers: LineNumbers,
+    pub soft_wrap: bool,
+    pub mouse: bool,
+    // Vi style Normal, Insert and Visual modes on top of the usual bindings.
+    pub vi: bool,
+    pub osc52: bool,
+    // Backups for every buffer; see Document::backup.
+    pub backup: bool,
+    pub quit_times: u8,
+    pub message_timeout: Duration,
+    pub swap_interval: Duration,
+    // How often unsaved changes to named files are written out, if at all.
+    pub autosave_interval: Option<Duration>,
+    pub colors: Colors,
+    filetypes: HashMap<String, FileTypeSettings>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            tab_width: DEFAULT_TAB_WIDTH,
+            soft_tabs: false,
+            line_numbers: LineNumbers::Off,
+            soft_wrap: false,
+            mouse: true,
+            vi: false,
+            osc52: false,
+            backup: false,
+            quit_times: 3,
+            message_timeout: Duration::from_secs(5),
+            swap_interval: Duration::from_secs(2),
+            autosave_interval: None,
+            colors: Colors::default(),
+            filetypes: HashMap::new(),
+        }
+    }
+}
+
+impl Config {
+    // Reads every config file that exists, later ones overriding earlier ones. Settings that
+    // make no sense are skipped and described in the returned errors.
+    pub fn load() -> (Self, Vec<String>) {
+        let mut config = Self::default();
+        let mut errors = Vec::new();
+
+        for path in config_files() {
+            let Ok(contents) = fs::read_to_string(&path) else {
+                continue;
+            };
+
+            let file = path.display().to_string();
+            match contents.parse::<Table>() {
+                Ok(table) => config.merge(&table, &file, &mut errors),
+                Err(error) => errors.push(format!("{}: {}", file, error.message())),
+            }
+        }
+
+        (config, errors)
+    }
+
+    // Command line flags beat every config file.
+    pub fn apply_args(&mut self, args: &[String]) {
+        for arg in args.iter().skip(1) {
+            if arg == "--osc52" {
+                self.osc52 = true;
+            } else if arg == "--no-mouse" {
+                self.mouse = false;
+            } else if arg == "--vi" {
+                self.vi = true;
+            } else if arg == "--backup" {
+                self.backup = true;
+            } else if let Some(width) = arg.strip_prefix("--tab-width=") {
+                if let Some(width) = width.parse::<usize>().ok().filter(|width| *width > 0) {
+                    self.tab_width = width;
+                    for filetype in self.filetypes.values_mut() {
+                        filetype.tab_width = None;
+                    }
+                }
+            }
+        }
+    }
+
+    // Sets the buffer-level settings of `document` for its file type, leaving the last word to
+    // its .editorconfig.
+    pub fn configure(&self, document: &mut Document) {
+        let filetype = self
+            .filetypes
+            .get(&document.file_type().to_lowercase())
+            .copied()
+            .unwrap_or_default();
+
+        document.tab_width = filetype.tab_width.unwrap_or(self.tab_width);
+        document.soft_tabs = filetype.soft_tabs.unwrap_or(self.soft_tabs);
+        document.indent_size = document.tab_width;
+        document.backup = self.backup;
+        document.apply_editorconfig();
+    }
+
+    fn merge(&mut self, table: &Table, file: &str, errors: &mut Vec<String>) {
+        for (key, value) in table {
+            let result = match key.as_str() {
+                "tab_width" => positive(value).map(|width| self.tab_width = width),
+                "soft_tabs" => flag(value).map(|soft_tabs| self.soft_tabs = soft_tabs),
+                "line_numbers" => match value.as_str() {
+                    Some("off") => Ok(LineNumbers::Off),
+                    Some("absolute") => Ok(LineNumbers::Absolute),
+                    Some("relative") => Ok(LineNumbers::Relative),
+                    _ => Err("must be \"off\", \"absolute\" or \"relative\""),
+                }
+                .map(|line_numbers| self.line_numbers = line_numbers),
+                "soft_wrap" => flag(value).map(|soft_wrap| self.soft_wrap = soft_wrap),
+                "mouse" => flag(value).map(|mouse| self.mouse = mouse),
+                "vi" => flag(value).map(|vi| self.vi = vi),
+                "osc52" => flag(value).map(|osc52| self.osc52 = osc52),
+                "backup" => flag(value).map(|backup| self.backup = backup),
+                "quit_times" => number(value)
+                    .and_then(|times| u8::try_from(times).map_err(|_| "is too large"))
+                    .map(|times| self.quit_times = times),
+                "message_timeout" => seconds(value).map(|timeout| self.message_timeout = timeout),
+                "swap_interval" => positive(value).map(|interval| self.swap_interval = Duration::from_secs(interval as u64)),
+                "autosave_interval" => seconds(value)
+                    .map(|interval| self.autosave_interval = Some(interval).filter(|interval| !interval.is_zero())),
+                "colors" => section(value).map(|colors| self.merge_colors(colors, file, errors)),
+                "filetype" => section(value).map(|filetypes| self.merge_filetypes(filetypes, file, errors)),
+                _ => Err("is not a setting"),
+            };
+
+            if let Err(error) = result {
+                errors.push(format!("{}: {} {}", file, key, error));
+            }
+        }
+    }
+
+    fn merge_colors(&mut self, table: &Table, file: &str, errors: &mut Vec<String>) {
+        for (key, value) in table {
+            let colors = &mut self.colors;
+            let target = match key.as_str() {
+                "status_bg" => &mut colors.status_bg,
+                "status_fg" => &mut colors.status_fg,
+                "inactive_status_bg" => &mut colors.inactive_status_bg,
+                "inactive_status_fg" => &mut colors.inactive_status_fg,
+                "line_number" => &mut colors.line_number,
+                "current_line_number" => &mut colors.current_line_number,
+                _ => {
+                    errors.push(format!("{}: colors.{} is not a color setting", file, key));
+                    continue;
+                },
+            };
+
+            match color(value) {
+                Ok(color) => *target = color,
+                Err(error) => errors.push(format!("{}: colors.{} {}", file, key, error)),
+            }
+        }
+    }
+
+    fn merge_filetypes(&mut self, table: &Table, file: &str, errors: &mut Vec<String>) {
+        for (name, value) in table {
+            let Ok(settings) = section(value) else {
+                errors.push(format!("{}: filetype.{} must be a table", file, name));
+                continue;
+            };
+
+            let filetype = self.filetypes.entry(name.to_lowercase()).or_default();
+            for (key, value) in settings {
+                let result = match key.as_str() {
+                    "tab_width" => positive(value).map(|width| filetype.tab_width = Some(width)),
+                    "soft_tabs" => flag(value).map(|soft_tabs| filetype.soft_tabs = Some(soft_tabs)),
+                    _ => Err("is not a file type setting"),
+                };
+
+                if let Err(error) = result {
+                    errors.push(format!("{}: filetype.{}.{} {}", file, name, key, error));
+                }
+            }
+        }
+    }
+}
+
+// $XDG_CONFIG_HOME/rhelico, falling back on ~/.config/rhelico.
+pub fn config_dir() -> Option<PathBuf> {
+    let base = env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+
+    Some(base.join("rhelico"))
+}
+
+// Lowest priority first: the system directories in $XDG_CONFIG_DIRS (listed most important
+// first), the user's config directory, then .rhelico.toml in the working directory.
+fn config_files() -> Vec<PathBuf> {
+    let system = env::var("XDG_CONFIG_DIRS")
+        .ok()
+        .filter(|dirs| !dirs.is_empty())
+        .unwrap_or_else(|| "/etc/xdg".to_string());
+
+    let mut files: Vec<PathBuf> = system
+        .split(':')
+        .rev()
+        .map(|dir| Path::new(dir).join("rhelico").join("config.toml"))
+        .collect();
+    files.extend(config_dir().map(|dir| dir.join("config.toml")));
+    files.push(PathBuf::from(".rhelico.toml"));
+    files
+}
+
+fn section(value: &Value) -> Result<&Table, &'static str> {
+    value.as_table().ok_or("must be a table")
+}
+
+fn flag(value: &Value) -> Result<bool, &'static str> {
+    value.as_bool().ok_or("must be true or false")
+}
+
+fn number(value: &Value) -> Result<usize, &'static str> {
+    value
+        .as_integer()
+        .and_then(|number| usize::try_from(number).ok())
+        .ok_or("must be a whole number")
+}
+
+fn positive(value: &Value) -> Result<usize, &'static str> {
+    number(value).ok().filter(|number| *number > 0).ok_or("must be a number above zero")
+}
+
+fn seconds(value: &Value) -> Result<Duration, &'static str> {
+    number(value).map(|seconds| Duration::from_secs(seconds as u64))
+}
+
+// Colors are written as "#rrggbb".
+fn color(value: &Value) -> Result<Color, &'static str> {
+    let error = "must be a color like \"#a0b0c0\"";
+    let hex = value.as_str().and_then(|text| text.strip_prefix('#')).ok_or(error)?;
+    if hex.len() != 6 {
+        return Err(error);
+    }
+
+    let channel = |at: usize| hex.get(at..at + 2).and_then(|channel| u8::from_str_radix(channel, 16).ok()).ok_or(error);
+    Ok(Color::Rgb { r: channel(0)?, g: channel(2)?, b: channel(4)? })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn merged(contents: &str) -> (Config, Vec<String>) {
+        let mut config = Config::default();
+        let mut errors = Vec::new();
+        config.merge(&contents.parse::<Table>().unwrap(), "config.toml", &mut errors);
+        (config, errors)
+    }
+
+    #[test]
+    fn settings_are_merged() {
+        let (config, errors) = merged(
+            "tab_width = 8\nsoft_tabs = true\nline_numbers = \"relative\"\nautosave_interval = 30\n\
+             [colors]\nstatus_bg = \"#102030\"\n[filetype.rust]\ntab_width = 2\n",
+        );
+        assert!(errors.is_empty());
+        assert_eq!(config.tab_width, 8);
+        assert!(config.soft_tabs);
+        assert!(matches!(config.line_numbers, LineNumbers::Relative));
+        assert_eq!(config.autosave_interval, Some(Duration::from_secs(30)));
+        assert_eq!(config.colors.status_bg, Color::Rgb { r: 0x10, g: 0x20, b: 0x30 });
+        assert_eq!(config.filetypes["rust"].tab_width, Some(2));
+    }
+
+    #[test]
+    fn bad_settings_are_reported_and_skipped() {
+        let (config, errors) = merged(
+            "tab_width = 0\nmouse = \"yes\"\nline_numbers = \"on\"\nquit_times = 300\nfont = \"mono\"\nvi = true\n",
+        );
+        assert!(config.vi);
+        assert_eq!(config.tab_width, DEFAULT_TAB_WIDTH);
+        assert!(config.mouse);
+        assert_eq!(config.quit_times, 3);
+        for error in [
+            "config.toml: tab_width must be a number above zero",
+            "config.toml: mouse must be true or false",
+            "config.toml: line_numbers must be \"off\", \"absolute\" or \"relative\"",
+            "config.toml: quit_times is too large",
+            "config.toml: font is not a setting",
+        ] {
+            assert!(errors.iter().any(|reported| reported == error), "missing {}", error);
+        }
+        assert_eq!(errors.len(), 5);
+    }
+
+    #[test]
+    fn bad_sections_are_reported_by_their_full_key() {
+        let (config, errors) = merged(
+            "colors = 3\n[filetype]\nc = 4\n[filetype.rust]\nsoft_tabs = 1\nindent = 2\n",
+        );
+        assert!(config.filetypes["rust"].soft_tabs.is_none());
+        for error in [
+            "config.toml: colors must be a table",
+            "config.toml: filetype.c must be a table",
+            "config.toml: filetype.rust.soft_tabs must be true or false",
+            "config.toml: filetype.rust.indent is not a file type setting",
+        ] {
+            assert!(errors.iter().any(|reported| reported == error), "missing {}", error);
+        }
+        assert_eq!(errors.len(), 4);
+    }
+
+    #[test]
+    fn bad_colors_are_reported() {
+        let (config, errors) = merged("[colors]\nstatus_fg = \"#12345\"\nline_number = \"#gg0000\"\ncursor = \"#000000\"\n");
+        assert_eq!(config.colors.status_fg, Colors::default().status_fg);
+        assert_eq!(errors.len(), 3);
+        assert!(errors.contains(&"config.toml: colors.cursor is not a color setting".to_string()));
+        assert!(errors.contains(&"config.toml: colors.status_fg must be a color like \"#a0b0c0\"".to_string()));
+    }
+
+    #[test]
+    fn a_tab_width_flag_beats_file_types() {
+        let (mut config, _) = merged("[filetype.rust]\ntab_width = 2\nsoft_tabs = true\n");
+        config.apply_args(&["rhelico".to_string(), "--tab-width=3".to_string(), "--backup".to_string()]);
+        assert_eq!(config.tab_width, 3);
+        assert_eq!(config.filetypes["rust"].tab_width, None);
+        assert_eq!(config.filetypes["rust"].soft_tabs, Some(true));
+        assert!(config.backup);
+    }
+}
@@ -0,0 +1,31 @@
+// A minimal line diff: everything between the common leading and trailing lines is
+// reported as removed from `current` and added in `other`.
+pub fn lines(current: &str, other: &str) -> String {
+    let old: Vec<&str> = current.lines().collect();
+    let new: Vec<&str> = other.lines().collect();
+
+    let prefix = old
+        .iter()
+        .zip(new.iter())
+        .take_while(|(old, new)| old == new)
+        .count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(old, new)| old == new)
+        .count();
+
+    if prefix == old.len() && prefix == new.len() {
+        return String::from("No differences.");
+    }
+
+    let mut result = format!("@@ line {} @@\n", prefix + 1);
+    for line in &old[prefix..old.len() - suffix] {
+        result.push_str(&format!("- {}\n", line));
+    }
+    for line in &new[prefix..new.len() - suffix] {
+        result.push_str(&format!("+ {}\n", line));
+    }
+    result
+}
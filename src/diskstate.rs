@@ -0,0 +1,59 @@
+use std::collections::hash_map::DefaultHasher;
+use std::fs::{self, Metadata};
+use std::hash::Hasher;
+use std::io::{self, Write};
+use std::time::SystemTime;
+
+// What the file looked like on disk the last time it was read or written.
+pub struct DiskState {
+    modified: Option<SystemTime>,
+    len: u64,
+    hash: u64,
+}
+
+impl DiskState {
+    pub fn new(metadata: &Metadata, hash: u64) -> Self {
+        Self {
+            modified: metadata.modified().ok(),
+            len: metadata.len(),
+            hash,
+        }
+    }
+
+    pub fn matches(&self, metadata: &Metadata) -> bool {
+        self.modified == metadata.modified().ok() && self.len == metadata.len()
+    }
+
+    pub fn hash(&self) -> u64 {
+        self.hash
+    }
+}
+
+// Feeds everything written into it through the hasher used for DiskState.
+#[derive(Default)]
+pub struct HashWriter {
+    hasher: DefaultHasher,
+}
+
+impl HashWriter {
+    pub fn finish(&self) -> u64 {
+        self.hasher.finish()
+    }
+}
+
+impl Write for HashWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.hasher.write(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+pub fn hash_file(file_name: &str) -> io::Result<u64> {
+    let mut hasher = HashWriter::default();
+    io::copy(&mut fs::File::open(file_name)?, &mut hasher)?;
+    Ok(hasher.finish())
+}
@@ -1,59 +1,340 @@
 use std::fs;
-use std::io::{Error, Write};
+use std::io::{BufReader, BufWriter, Error, Write};
+use std::path::{Path, PathBuf};
+use std::process;
 use crate::row::Row;
 use crate::position::Position;
+use crate::editor::SearchDirection;
+use crate::filetype::FileType;
+use crate::history::{self, Edit, EditKind, History};
+use crate::swap;
+use crate::diskstate::{self, DiskState, HashWriter};
+use crate::config::DEFAULT_TAB_WIDTH;
+use crate::editorconfig::Properties;
 
-use crossterm::event::KeyCode;
+use regex::Regex;
+use ropey::Rope;
+
+#[derive(Default, Clone, Copy, PartialEq)]
+pub enum LineEnding {
+    #[default]
+    Lf,
+    Crlf,
+}
+
+impl LineEnding {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LineEnding::Lf => "\n",
+            LineEnding::Crlf => "\r\n",
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            LineEnding::Lf => "LF",
+            LineEnding::Crlf => "CRLF",
+        }
+    }
+}
+
+pub struct SearchMatch {
+    pub start: Position,
+    pub end: Position,
+    pub replacement: String,
+}
 
 
-#[derive(Default)]
 pub struct Document {
-    rows: Vec<Row>,
+    text: Rope,
     pub file_name: Option<String>,
-    dirty: bool,
+    // Keep the previous contents in `file~` when saving over an existing file.
+    pub backup: bool,
+    pub tab_width: usize,
+    // Tab inserts spaces up to the next tab stop instead of a tab character.
+    pub soft_tabs: bool,
+    // Columns a soft tab indents by.
+    pub indent_size: usize,
+    // What .editorconfig files have to say about this file.
+    pub editorconfig: Properties,
+    history: History,
+    line_ending: LineEnding,
+    final_newline: bool,
+    bom: bool,
+    file_type: FileType,
+    // Whether each row starts inside a multi-line comment, as far as it has been worked out.
+    // Entries after `comment_checked` may be out of date; edits shift them along with their
+    // rows rather than dropping them.
+    comment_state: Vec<bool>,
+    comment_checked: usize,
+    // Rows before this were edited since they were last worked out, so the walk has to go at
+    // least this far before it can trust what comes after.
+    comment_edited_until: usize,
+    // Bumped on every change to the text, so the swap file is only rewritten when needed.
+    revision: usize,
+    swap_revision: Option<usize>,
+    // Another running session journals this file, so this one leaves the swap file alone.
+    swap_taken: bool,
+    disk_state: Option<DiskState>,
+}
+
+impl Default for Document {
+    fn default() -> Self {
+        Self {
+            text: Rope::new(),
+            file_name: None,
+            backup: false,
+            tab_width: DEFAULT_TAB_WIDTH,
+            soft_tabs: false,
+            indent_size: DEFAULT_TAB_WIDTH,
+            editorconfig: Properties::default(),
+            history: History::default(),
+            line_ending: LineEnding::default(),
+            final_newline: true,
+            bom: false,
+            file_type: FileType::default(),
+            comment_state: Vec::new(),
+            comment_checked: 0,
+            comment_edited_until: 0,
+            revision: 0,
+            swap_revision: None,
+            swap_taken: false,
+            disk_state: None,
+        }
+    }
 }
 
 impl Document {
+    // Line breaks are kept as they are in the rope, so saving writes them back untouched.
     pub fn open(filename: &str) -> Result<Self, std::io::Error> {
-        let contents = fs::read_to_string(filename)?;
+        let file = fs::File::open(filename)?;
+        let metadata = file.metadata()?;
+        let mut text = Rope::from_reader(BufReader::new(file))?;
+
+        let bom = text.len_chars() > 0 && text.char(0) == '\u{feff}';
+        if bom {
+            text.remove(0..1);
+        }
+
+        let line_ending = detect_line_ending(&text);
+
+        let chars = text.len_chars();
+        let final_newline = chars == 0 || text.char(chars - 1) == '\n';
+
+        let mut document = Self{
+            text,
+            file_name: Some(filename.to_string()),
+            backup: false,
+            tab_width: DEFAULT_TAB_WIDTH,
+            soft_tabs: false,
+            indent_size: DEFAULT_TAB_WIDTH,
+            editorconfig: Properties::default(),
+            history: History::default(),
+            line_ending,
+            final_newline,
+            bom,
+            file_type: FileType::from(filename),
+            comment_state: Vec::new(),
+            comment_checked: 0,
+            comment_edited_until: 0,
+            revision: 0,
+            swap_revision: None,
+            swap_taken: false,
+            disk_state: None,
+        };
+
+        document.disk_state = Some(DiskState::new(&metadata, document.content_hash()?));
+        document.load_editorconfig();
+        Ok(document)
+    }
+
+    pub fn load_editorconfig(&mut self) {
+        self.editorconfig = self
+            .file_name
+            .as_ref()
+            .map_or_else(Properties::default, |file_name| Properties::for_file(Path::new(file_name)));
+    }
+
+    // Lets .editorconfig override the indentation and line ending settings it has an opinion on.
+    pub fn apply_editorconfig(&mut self) {
+        let properties = self.editorconfig;
+        self.line_ending = properties.end_of_line.unwrap_or(self.line_ending);
+        self.tab_width = properties.tab_width.unwrap_or(self.tab_width);
+        self.indent_size = properties.indent_size.unwrap_or(self.tab_width);
+        self.soft_tabs = properties.soft_tabs.unwrap_or(self.soft_tabs);
+    }
+
+    // A nameless document holding `text`, used to show things like diffs in the editor.
+    pub fn from_text(text: &str) -> Self {
+        Self {
+            text: Rope::from_str(text),
+            ..Self::default()
+        }
+    }
+
+    pub fn row(&self, index: usize) -> Option<Row> {
+        if index < self.len() {
+            Some(Row::from(self.text.line(index)))
+        } else {
+            None
+        }
+    }
+
+    pub fn highlighted_row(&self, index: usize, word: Option<&Regex>) -> Option<Row> {
+        let mut row = self.row(index)?;
+        let start_with_comment = self.comment_state.get(index).copied().unwrap_or(false);
+        row.highlight(self.file_type.highlighting_options(), word, start_with_comment);
+        Some(row)
+    }
 
-        let mut rows = Vec::new();
+    // Carries multi-line comment state down the document far enough to draw rows before `until`.
+    pub fn highlight(&mut self, until: usize) {
+        if self.file_type.highlighting_options().multiline_comment().is_none() {
+            return;
+        }
 
-        for value in contents.lines() {
-            rows.push(Row::from(value));
+        let until = until.min(self.len());
+        if self.comment_state.is_empty() {
+            self.comment_state.push(false);
         }
+        self.comment_checked = self.comment_checked.min(self.comment_state.len() - 1);
 
-        Ok(
-            Self{
-                rows,
-                file_name: Some(filename.to_string()),
-                dirty: false,
+        while self.comment_checked < until {
+            let index = self.comment_checked;
+            let in_comment = self.comment_state[index];
+            let ends_in_comment = match self.row(index) {
+                Some(mut row) => row.highlight(self.file_type.highlighting_options(), None, in_comment),
+                None => in_comment,
+            };
+            self.comment_checked += 1;
+
+            let Some(cached) = self.comment_state.get_mut(index + 1) else {
+                self.comment_state.push(ends_in_comment);
+                continue;
+            };
+            let unchanged = *cached == ends_in_comment;
+            *cached = ends_in_comment;
+            // Below the edited rows, a row that ends the way it did before leaves the rest as it was.
+            if self.comment_checked >= self.comment_edited_until {
+                self.comment_edited_until = 0;
+                if unchanged {
+                    self.comment_checked = self.comment_state.len() - 1;
+                }
             }
-        )
+        }
     }
 
-    pub fn row(&self, index: usize) -> Option<&Row> {
-        self.rows.get(index)
+    fn reset_comment_state(&mut self) {
+        self.comment_state.clear();
+        self.comment_checked = 0;
+        self.comment_edited_until = 0;
+    }
+
+    pub fn file_type(&self) -> String {
+        self.file_type.name()
     }
 
     pub fn is_empty(&self) -> bool {
-        self.rows.is_empty()
+        self.len() == 0
     }
 
+    // A trailing newline terminates the last row rather than starting an empty one.
     pub fn len(&self) -> usize {
-        self.rows.len()
+        if self.text.len_chars() == 0 {
+            0
+        } else if self.ends_with_newline() {
+            self.text.len_lines() - 1
+        } else {
+            self.text.len_lines()
+        }
     }
 
-    pub fn insert_newline(&mut self, at: &Position) {
+    pub fn find(&self, query: &str, at: &Position, direction: SearchDirection) -> Option<Position> {
+        if at.y >= self.len() {
+            return None;
+        }
 
-        if at.y == self.len() {
-            self.rows.push(Row::default());
-            return;
+        let mut position = Position { x: at.x, y: at.y };
+
+        let (start, end) = if direction == SearchDirection::Forward {
+            (at.y, self.len())
+        } else {
+            (0, at.y.saturating_add(1))
+        };
+
+        for _ in start..end {
+            if let Some(row) = self.row(position.y) {
+                if let Some(x) = row.find(query, position.x, direction) {
+                    position.x = x;
+                    return Some(position);
+                }
+
+                if direction == SearchDirection::Forward {
+                    position.y = position.y.saturating_add(1);
+                    position.x = 0;
+                } else {
+                    position.y = position.y.saturating_sub(1);
+                    position.x = self.row(position.y).map_or(0, |row| row.len());
+                }
+            } else {
+                return None;
+            }
+        }
+
+        None
+    }
+
+    pub fn find_regex(&self, regex: &Regex, at: &Position, replacement: &str) -> Option<SearchMatch> {
+        for y in at.y..self.len() {
+            let row = self.row(y)?;
+            let x = if y == at.y { at.x } else { 0 };
+
+            if let Some((start, end, replacement)) = row.find_regex(regex, x, replacement) {
+                return Some(SearchMatch {
+                    start: Position { x: start, y },
+                    end: Position { x: end, y },
+                    replacement,
+                });
+            }
+        }
+
+        None
+    }
+
+    // Swaps a match for its replacement, returning the position just past the new text.
+    pub fn replace(&mut self, found: &SearchMatch) -> Position {
+        if found.start != found.end {
+            let text = self.delete_text(&found.start, &found.end);
+            self.history.record(Edit { kind: EditKind::Delete, at: found.start, text }, found.start, found.start);
+        }
+
+        if found.replacement.is_empty() {
+            return found.start;
+        }
+
+        let end = self.insert_text(&found.start, &found.replacement);
+        let text = found.replacement.clone();
+        self.history.record(Edit { kind: EditKind::Insert, at: found.start, text }, found.start, end);
+        end
+    }
+
+    // The position one grapheme further on, stepping onto the next row at a row's end.
+    pub fn next_position(&self, at: &Position) -> Position {
+        if at.x < self.row(at.y).map_or(0, |row| row.len()) {
+            Position { x: at.x + 1, y: at.y }
+        } else {
+            Position { x: 0, y: at.y + 1 }
         }
+    }
 
-        let new_row = self.rows.get_mut(at.y).unwrap().split(at.x);
-        self.rows.insert(at.y + 1, new_row);
+    pub fn insert_newline(&mut self, at: &Position) {
+        if at.y > self.len() {
+            return;
+        }
 
+        let (at, text) = self.anchor_insert(at, self.line_ending.as_str());
+        let end = self.insert_text(&at, &text);
+        self.history.record(Edit { kind: EditKind::Insert, at, text }, at, end);
     }
 
 
@@ -62,22 +343,32 @@ impl Document {
         if at.y > self.len() {
             return;
         }
-        self.dirty = true;
 
         if c == '\n' {
             self.insert_newline(at);
             return;
         }
 
-        if at.y == self.len() {
-            let mut row = Row::default();
-            row.insert(0, c);
-            self.rows.push(row);
+        let (at, text) = self.anchor_insert(at, &c.to_string());
+        let end = self.insert_text(&at, &text);
+        self.history.record_typed(Edit { kind: EditKind::Insert, at, text }, end);
+    }
 
-        } else {
-            let row = self.rows.get_mut(at.y).unwrap();
-            row.insert(at.x, c);
+    // Splices in a whole block of text as one undo step, returning the position just past it.
+    // Its line breaks, whether LF, CRLF or the bare CR terminals paste with, become the document's.
+    pub fn insert_str(&mut self, at: &Position, text: &str) -> Position {
+        if at.y > self.len() || text.is_empty() {
+            return *at;
         }
+
+        let text = text
+            .replace("\r\n", "\n")
+            .replace('\r', "\n")
+            .replace('\n', self.line_ending.as_str());
+        let (start, text) = self.anchor_insert(at, &text);
+        let end = self.insert_text(&start, &text);
+        self.history.record(Edit { kind: EditKind::Insert, at: start, text }, *at, end);
+        end
     }
 
     pub fn delete(&mut self, at: &Position) {
@@ -87,36 +378,692 @@ impl Document {
             return;
         }
 
-        self.dirty = true;
+        let end = if at.x < self.row(at.y).map_or(0, |row| row.len()) {
+            Position { x: at.x + 1, y: at.y }
+        } else if at.y < len - 1 {
+            Position { x: 0, y: at.y + 1 }
+        } else {
+            return;
+        };
+
+        let text = self.delete_text(at, &end);
+        self.history.record(Edit { kind: EditKind::Delete, at: *at, text }, *at, *at);
+    }
+
+    // Deletes everything from `start` up to `end`, returning where the cursor should land.
+    pub fn delete_range(&mut self, start: &Position, end: &Position) -> Position {
+        if start == end {
+            return *start;
+        }
+
+        let text = self.delete_text(start, end);
+        self.history.record(Edit { kind: EditKind::Delete, at: *start, text }, *end, *start);
+        *start
+    }
 
-        if at.x == self.rows.get_mut(at.y).unwrap().len() && at.y < len - 1 {
-            let next_row = self.rows.remove(at.y + 1);
-            let row = self.rows.get_mut(at.y).unwrap();
-            row.append(&next_row);
+    // Deletes the grapheme before `at`, returning where the cursor should land.
+    pub fn backspace(&mut self, at: &Position) -> Position {
+        let start = if at.x > 0 {
+            Position { x: at.x - 1, y: at.y }
+        } else if at.y > 0 {
+            Position { x: self.row(at.y - 1).map_or(0, |row| row.len()), y: at.y - 1 }
         } else {
-            let row = self.rows.get_mut(at.y).unwrap();
-            row.delete(at.x);
+            return *at;
+        };
+
+        if at.y >= self.len() {
+            return start;
+        }
+
+        let text = self.delete_text(&start, at);
+        self.history.record(Edit { kind: EditKind::Delete, at: start, text }, *at, start);
+        start
+    }
 
+    pub fn undo(&mut self) -> Option<Position> {
+        let step = self.history.undo()?;
+
+        for edit in step.edits.iter().rev() {
+            match edit.kind {
+                EditKind::Insert => {
+                    self.delete_text(&edit.at, &edit.end());
+                },
+                EditKind::Delete => {
+                    self.insert_text(&edit.at, &edit.text);
+                },
+            }
         }
+
+        if let Some((before, _)) = step.line_ending {
+            self.line_ending = before;
+        }
+
+        let cursor = step.cursor_before;
+        self.history.push_undone(step);
+        Some(cursor)
+    }
+
+    pub fn redo(&mut self) -> Option<Position> {
+        let step = self.history.redo()?;
+
+        for edit in &step.edits {
+            match edit.kind {
+                EditKind::Insert => {
+                    self.insert_text(&edit.at, &edit.text);
+                },
+                EditKind::Delete => {
+                    self.delete_text(&edit.at, &edit.end());
+                },
+            }
+        }
+
+        if let Some((_, after)) = step.line_ending {
+            self.line_ending = after;
+        }
+
+        let cursor = step.cursor_after;
+        self.history.push_redone(step);
+        Some(cursor)
+    }
+
+    // Every edit until `end_undo_group` is undone and redone as a single step.
+    pub fn begin_undo_group(&mut self, cursor: &Position) {
+        self.history.begin_group(*cursor);
+    }
+
+    pub fn end_undo_group(&mut self) {
+        self.history.end_group();
+    }
+
+    // Ends the current run of typing so the next character starts a new undo step.
+    pub fn break_undo_group(&mut self) {
+        self.history.seal();
     }
 
     pub fn save(&mut self) -> Result<(), Error> {
+        let Some(file_name) = self.file_name.clone() else {
+            return Ok(());
+        };
+        let target = self.write_file(&file_name, self.editorconfig)?;
+        self.remove_swap();
+        self.saved(&target);
+        Ok(())
+    }
+
+    // Saves under a new name, which the document only takes on once the file is written.
+    pub fn save_as(&mut self, file_name: &str) -> Result<(), Error> {
+        let properties = Properties::for_file(Path::new(file_name));
+        let target = self.write_file(file_name, properties)?;
+        self.remove_swap();
+        self.file_name = Some(file_name.to_string());
+        self.editorconfig = properties;
+        self.saved(&target);
+        Ok(())
+    }
+
+    // Writes the text to another file, leaving the document as it was.
+    pub fn write_copy(&self, file_name: &str) -> Result<(), Error> {
+        self.write_file(file_name, Properties::for_file(Path::new(file_name))).map(|_| ())
+    }
+
+    // Writes to a temporary file next to the target and renames it into place, so a failed
+    // save never leaves a truncated file behind. Returns where the file ended up.
+    fn write_file(&self, file_name: &str, properties: Properties) -> Result<PathBuf, Error> {
+        let target = fs::canonicalize(file_name).unwrap_or_else(|_| PathBuf::from(file_name));
+        let directory = match target.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+            _ => PathBuf::from("."),
+        };
+        let temp_path = temp_path_for(&target, &directory);
+        let permissions = fs::metadata(&target).ok().map(|metadata| metadata.permissions());
+
+        if let Err(error) = create_with(&temp_path, permissions, |file| self.write_contents(file, properties)) {
+            fs::remove_file(&temp_path).ok();
+            return Err(error);
+        }
+
+        if self.backup && target.exists() {
+            let mut backup_path = target.clone().into_os_string();
+            backup_path.push("~");
+            if let Err(error) = fs::copy(&target, backup_path) {
+                fs::remove_file(&temp_path).ok();
+                return Err(error);
+            }
+        }
+
+        if let Err(error) = fs::rename(&temp_path, &target) {
+            fs::remove_file(&temp_path).ok();
+            return Err(error);
+        }
+
+        #[cfg(unix)]
+        if let Ok(directory) = fs::File::open(&directory) {
+            directory.sync_all().ok();
+        }
 
+        Ok(target)
+    }
+
+    // The file is in place at `target` now, so the save counts even if it can't be looked at again.
+    fn saved(&mut self, target: &Path) {
         if let Some(file_name) = &self.file_name {
-            let mut file = fs::File::create(file_name)?;
-            for row in &self.rows {
-                file.write_all(row.as_bytes())?;
-                file.write_all(b"\n")?;
+            self.file_type = FileType::from(file_name);
+        }
+        self.reset_comment_state();
+        self.apply_save_rules();
+        self.history.mark_saved();
+        self.disk_state = match (fs::metadata(target), self.content_hash()) {
+            (Ok(metadata), Ok(hash)) => Some(DiskState::new(&metadata, hash)),
+            _ => None,
+        };
+    }
+
+    // Takes up the .editorconfig rules about how the file is written once it has been. The text
+    // itself is left alone; write_contents applies them to the bytes on their way out.
+    fn apply_save_rules(&mut self) {
+        let properties = self.editorconfig;
+        self.bom = properties.bom.unwrap_or(self.bom);
+        self.final_newline = properties.insert_final_newline.unwrap_or(self.final_newline);
+        self.line_ending = properties.end_of_line.unwrap_or(self.line_ending);
+    }
+
+    // The exact bytes that belong on disk for this document: line endings, byte order mark,
+    // trailing whitespace and the final newline as the .editorconfig `properties` ask.
+    fn write_contents<W: Write>(&self, writer: &mut W, properties: Properties) -> Result<(), Error> {
+        let trim = properties.trim_trailing_whitespace == Some(true);
+        let no_final_newline = properties.insert_final_newline == Some(false);
+
+        if properties.bom.unwrap_or(self.bom) {
+            writer.write_all("\u{feff}".as_bytes())?;
+        }
+
+        if !trim && properties.end_of_line.is_none() && !no_final_newline {
+            for chunk in self.text.chunks() {
+                writer.write_all(chunk.as_bytes())?;
             }
-            self.dirty = false;
+        } else {
+            let mut lines = self.text.lines().peekable();
+            while let Some(line) = lines.next() {
+                let line = line.to_string();
+                let text = line.strip_suffix('\n').map_or(&line[..], |text| text.strip_suffix('\r').unwrap_or(text));
+                let ending = &line[text.len()..];
+                writer.write_all(if trim { text.trim_end_matches([' ', '\t']) } else { text }.as_bytes())?;
+
+                let last = lines.peek().is_none_or(|next| next.len_chars() == 0);
+                if ending.is_empty() || (last && no_final_newline) {
+                    continue;
+                }
+                writer.write_all(properties.end_of_line.map_or(ending, |line_ending| line_ending.as_str()).as_bytes())?;
+            }
+        }
+
+        let final_newline = properties.insert_final_newline.unwrap_or(self.final_newline);
+        if final_newline && !self.is_empty() && !self.ends_with_newline() {
+            let line_ending = properties.end_of_line.unwrap_or(self.line_ending);
+            writer.write_all(line_ending.as_str().as_bytes())?;
+        }
+        Ok(())
+    }
+
+    fn content_hash(&self) -> Result<u64, Error> {
+        let mut hasher = HashWriter::default();
+        self.write_contents(&mut hasher, self.editorconfig)?;
+        Ok(hasher.finish())
+    }
+
+    // True once the file on disk no longer holds what was last read or written. Metadata
+    // is checked first; the contents are only hashed when it looks different.
+    pub fn changed_on_disk(&mut self) -> bool {
+        let (Some(file_name), Some(disk_state)) = (&self.file_name, &self.disk_state) else {
+            return false;
+        };
+        let Ok(metadata) = fs::metadata(file_name) else {
+            return false;
+        };
+        if disk_state.matches(&metadata) {
+            return false;
         }
 
+        match diskstate::hash_file(file_name) {
+            Ok(hash) if hash == disk_state.hash() => {
+                self.disk_state = Some(DiskState::new(&metadata, hash));
+                false
+            },
+            Ok(_) => true,
+            Err(_) => false,
+        }
+    }
+
+    // Accepts whatever is on disk now as the known state, so the change is not reported again.
+    pub fn acknowledge_disk_change(&mut self) {
+        if let Some(file_name) = &self.file_name {
+            if let (Ok(metadata), Ok(hash)) = (fs::metadata(file_name), diskstate::hash_file(file_name)) {
+                self.disk_state = Some(DiskState::new(&metadata, hash));
+            }
+        }
+    }
+
+    // Replaces the text with the file's current contents; the reload itself can be undone.
+    pub fn reload(&mut self) -> Result<(), Error> {
+        let file_name = self.file_name.clone().unwrap_or_default();
+        let fresh = Self::open(&file_name)?;
+
+        self.history.begin_group(Position::default());
+        self.replace_text(&fresh.contents());
+        self.history.record_line_ending(self.line_ending, fresh.line_ending);
+        self.history.end_group();
+        self.line_ending = fresh.line_ending;
+        self.final_newline = fresh.final_newline;
+        self.bom = fresh.bom;
+        self.disk_state = fresh.disk_state;
+        self.history.mark_saved();
         Ok(())
     }
 
     pub fn is_dirty(&self) -> bool {
-        self.dirty
+        self.history.is_dirty()
+    }
+
+    pub fn line_ending(&self) -> LineEnding {
+        self.line_ending
+    }
+
+    // Short summary of how the file is encoded on disk, e.g. "CRLF BOM noeol".
+    pub fn format_description(&self) -> String {
+        let mut description = self.line_ending.name().to_string();
+        if self.bom {
+            description.push_str(" BOM");
+        }
+        if !self.final_newline {
+            description.push_str(" noeol");
+        }
+        description
+    }
+
+    // Rewrites every line break in the new style as a single undoable step. Only the breaks
+    // themselves are replaced, so positions in the text stay where they were.
+    pub fn set_line_ending(&mut self, line_ending: LineEnding, cursor: &Position) {
+        self.history.begin_group(*cursor);
+
+        for y in 0..self.len() {
+            let line = self.text.line(y);
+            let ending = line.slice(Row::from(line).as_str().chars().count()..).to_string();
+            if ending.is_empty() || ending == line_ending.as_str() {
+                continue;
+            }
+
+            let at = Position { x: self.row(y).map_or(0, |row| row.len()), y };
+            let text = self.delete_text(&at, &Position { x: 0, y: y + 1 });
+            self.history.record(Edit { kind: EditKind::Delete, at, text }, *cursor, *cursor);
+            self.insert_text(&at, line_ending.as_str());
+            let text = line_ending.as_str().to_string();
+            self.history.record(Edit { kind: EditKind::Insert, at, text }, *cursor, *cursor);
+        }
+
+        self.history.record_line_ending(self.line_ending, line_ending);
+        self.history.end_group();
+        self.line_ending = line_ending;
+    }
+
+    // Swaps the whole text for `contents` as a single undoable edit.
+    pub fn replace_contents(&mut self, contents: &str) {
+        self.history.begin_group(Position::default());
+        self.replace_text(contents);
+        self.history.end_group();
+    }
+
+    fn replace_text(&mut self, contents: &str) {
+        let start = Position::default();
+        let original = self.text.to_string();
+        let end = history::end_of(&start, &original);
+
+        self.delete_text(&start, &end);
+        self.history.record(Edit { kind: EditKind::Delete, at: start, text: original }, start, start);
+        let end = self.insert_text(&start, contents);
+        self.history.record(Edit { kind: EditKind::Insert, at: start, text: contents.to_string() }, start, end);
+    }
+
+    pub fn has_swap(&self) -> bool {
+        self.file_name.as_deref().is_some_and(|file_name| swap::path_for(file_name).exists())
+    }
+
+    // The text journaled in the swap file.
+    pub fn read_swap(&self) -> Result<String, Error> {
+        match &self.file_name {
+            Some(file_name) => {
+                let contents = fs::read_to_string(swap::path_for(file_name))?;
+                Ok(swap::parse(&contents).1.to_string())
+            },
+            None => Ok(String::new()),
+        }
+    }
+
+    // The session journaling this file, if it is another one that is still running.
+    pub fn swap_in_use(&self) -> Option<swap::Owner> {
+        let owner = swap::owner(&swap::path_for(self.file_name.as_deref()?))?;
+        (owner != swap::Owner::current() && owner.is_alive()).then_some(owner)
+    }
+
+    // Journals the current text next to the file, skipping the write if nothing changed. Like
+    // a save it goes through a temporary file, so the last good journal survives a failed write.
+    // A swap file another running session is writing is left to it.
+    pub fn write_swap(&mut self) -> Result<(), Error> {
+        if self.swap_revision == Some(self.revision) {
+            return Ok(());
+        }
+
+        if self.swap_revision.is_none() {
+            let owner = self.swap_in_use();
+            let reported = self.swap_taken;
+            self.swap_taken = owner.is_some();
+            match owner {
+                Some(owner) if !reported => return Err(Error::other(format!("it is in use by {}", owner))),
+                Some(_) => return Ok(()),
+                None => (),
+            }
+        }
+
+        if let Some(file_name) = &self.file_name {
+            let target = swap::path_for(file_name);
+            let directory = match target.parent() {
+                Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+                _ => PathBuf::from("."),
+            };
+            let temp_path = temp_path_for(&target, &directory);
+
+            let written = create_with(&temp_path, None, |file| {
+                file.write_all(swap::Owner::current().header().as_bytes())?;
+                for chunk in self.text.chunks() {
+                    file.write_all(chunk.as_bytes())?;
+                }
+                Ok(())
+            })
+            .and_then(|()| fs::rename(&temp_path, &target));
+            if let Err(error) = written {
+                fs::remove_file(&temp_path).ok();
+                return Err(error);
+            }
+            self.swap_revision = Some(self.revision);
+        }
+
+        Ok(())
     }
 
+    pub fn remove_swap(&mut self) {
+        if self.swap_in_use().is_some() {
+            return;
+        }
+        if let Some(file_name) = &self.file_name {
+            fs::remove_file(swap::path_for(file_name)).ok();
+        }
+        self.swap_revision = None;
+    }
+
+    // Only a swap file this session wrote itself is cleaned up without asking.
+    pub fn owns_swap(&self) -> bool {
+        self.swap_revision.is_some()
+    }
+
+    pub fn contents(&self) -> String {
+        self.text.to_string()
+    }
+
+    // The text from `start` up to `end`, with '\n' line breaks.
+    pub fn text_range(&self, start: &Position, end: &Position) -> String {
+        let range = self.char_index(start)..self.char_index(end);
+        self.text.slice(range).to_string().replace("\r\n", "\n")
+    }
 
-}
\ No newline at end of file
+    fn ends_with_newline(&self) -> bool {
+        let chars = self.text.len_chars();
+        chars > 0 && self.text.char(chars - 1) == '\n'
+    }
+
+    // Typing on the line past the end of the document appends a new row. When the text
+    // has no final newline yet, that row has to be started by the insert itself.
+    fn anchor_insert(&self, at: &Position, text: &str) -> (Position, String) {
+        let len = self.len();
+        if at.y < len || self.text.len_chars() == 0 || self.ends_with_newline() {
+            return (*at, text.to_string());
+        }
+
+        let last = Position { x: self.row(len - 1).map_or(0, |row| row.len()), y: len - 1 };
+        (last, format!("{}{}", self.line_ending.as_str(), text))
+    }
+
+    fn insert_text(&mut self, at: &Position, text: &str) -> Position {
+        let index = self.char_index(at);
+        self.text.insert(index, text);
+        let lines = text.matches('\n').count();
+        let row = (at.y + 1).min(self.comment_state.len());
+        self.comment_state.splice(row..row, std::iter::repeat_n(false, lines));
+        if self.comment_edited_until > at.y {
+            self.comment_edited_until += lines;
+        }
+        self.stale_comment_state(at.y, at.y + lines);
+        self.revision += 1;
+        history::end_of(at, text)
+    }
+
+    fn delete_text(&mut self, start: &Position, end: &Position) -> String {
+        let range = self.char_index(start)..self.char_index(end);
+        self.revision += 1;
+        let removed = self.text.slice(range.clone()).to_string();
+        self.text.remove(range);
+
+        let lines = removed.matches('\n').count();
+        let row = (start.y + 1).min(self.comment_state.len());
+        self.comment_state.drain(row..(row + lines).min(self.comment_state.len()));
+        self.stale_comment_state(start.y, start.y);
+        removed
+    }
+
+    // Rows `first` to `last` changed, so their comment state has to be worked out again.
+    fn stale_comment_state(&mut self, first: usize, last: usize) {
+        self.comment_checked = self.comment_checked.min(first);
+        self.comment_edited_until = self.comment_edited_until.max(last + 1);
+    }
+
+    // Maps a row/grapheme position onto the rope's char offsets.
+    fn char_index(&self, at: &Position) -> usize {
+        if at.y >= self.text.len_lines() {
+            return self.text.len_chars();
+        }
+
+        let line_start = self.text.line_to_char(at.y);
+        match self.row(at.y) {
+            Some(row) => line_start + row.char_index(at.x),
+            None => line_start,
+        }
+    }
+}
+
+// The more common of the two line breaks in `text`, so a mixed file isn't labelled from
+// whichever line happens to come first.
+fn detect_line_ending(text: &Rope) -> LineEnding {
+    let (mut crlf, mut lf) = (0, 0);
+    let mut previous = None;
+    for c in text.chars() {
+        if c == '\n' {
+            if previous == Some('\r') {
+                crlf += 1;
+            } else {
+                lf += 1;
+            }
+        }
+        previous = Some(c);
+    }
+
+    if crlf > lf {
+        LineEnding::Crlf
+    } else {
+        LineEnding::Lf
+    }
+}
+
+// A name for a scratch file beside `target` in `directory`, unique to this process.
+fn temp_path_for(target: &Path, directory: &Path) -> PathBuf {
+    let base_name = target
+        .file_name()
+        .map_or_else(String::new, |name| name.to_string_lossy().into_owned());
+    directory.join(format!(".{}.{}.tmp", base_name, process::id()))
+}
+
+// Creates a new file at `path`, fills it with `write` and flushes it all the way to the disk.
+fn create_with<F>(path: &Path, permissions: Option<fs::Permissions>, write: F) -> Result<(), Error>
+where
+    F: FnOnce(&mut BufWriter<fs::File>) -> Result<(), Error>,
+{
+    let file = fs::OpenOptions::new().write(true).create_new(true).open(path)?;
+    if let Some(permissions) = permissions {
+        file.set_permissions(permissions)?;
+    }
+
+    let mut file = BufWriter::new(file);
+    write(&mut file)?;
+    file.flush()?;
+    file.get_ref().sync_all()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Opens a document read from a file holding `bytes`; the file is gone again afterwards.
+    fn open_bytes(name: &str, bytes: &[u8]) -> Document {
+        let path = std::env::temp_dir().join(format!("rhelico-test-{}-{}", process::id(), name));
+        fs::write(&path, bytes).unwrap();
+        let document = Document::open(path.to_str().unwrap());
+        fs::remove_file(&path).ok();
+        document.unwrap()
+    }
+
+    fn written(document: &Document) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        document.write_contents(&mut bytes, document.editorconfig).unwrap();
+        bytes
+    }
+
+    fn rust(text: &str) -> Document {
+        let mut document = Document::from_text(text);
+        document.file_type = FileType::from("test.rs");
+        document
+    }
+
+    // Comment state worked out incrementally agrees with working it out from scratch.
+    fn assert_comment_state(document: &mut Document) {
+        let len = document.len();
+        document.highlight(len);
+        let mut fresh = rust(&document.contents());
+        fresh.highlight(len);
+        assert_eq!(document.comment_state[..=len], fresh.comment_state[..=len]);
+    }
+
+    #[test]
+    fn comment_state_follows_edits() {
+        let mut document = rust("a\nb\nc\nd\ne\nf\n");
+        assert_comment_state(&mut document);
+
+        document.insert_str(&Position { x: 0, y: 1 }, "/*");
+        assert_comment_state(&mut document);
+        document.insert_str(&Position { x: 1, y: 4 }, "*/\nx\ny");
+        assert_comment_state(&mut document);
+        document.delete_range(&Position { x: 0, y: 1 }, &Position { x: 2, y: 1 });
+        assert_comment_state(&mut document);
+        document.delete_range(&Position { x: 0, y: 2 }, &Position { x: 0, y: 5 });
+        assert_comment_state(&mut document);
+        document.undo();
+        document.undo();
+        assert_comment_state(&mut document);
+    }
+
+    #[test]
+    fn comment_state_stops_once_it_settles() {
+        let mut document = rust(&"line\n".repeat(1000));
+        document.highlight(1000);
+        document.insert(&Position { x: 0, y: 0 }, 'x');
+        document.highlight(10);
+        assert_eq!(document.comment_checked, 1000);
+    }
+
+    #[test]
+    fn save_rules_apply_to_the_bytes_only() {
+        let mut document = Document::from_text("one  \r\ntwo\t\n");
+        document.editorconfig.trim_trailing_whitespace = Some(true);
+        document.editorconfig.end_of_line = Some(LineEnding::Lf);
+        assert_eq!(written(&document), b"one\ntwo\n");
+        assert_eq!(document.contents(), "one  \r\ntwo\t\n");
+        assert!(!document.is_dirty());
+    }
+
+    #[test]
+    fn save_rules_for_the_final_newline() {
+        let mut document = Document::from_text("one\ntwo\n");
+        document.editorconfig.insert_final_newline = Some(false);
+        assert_eq!(written(&document), b"one\ntwo");
+
+        let mut document = Document::from_text("one\ntwo");
+        document.final_newline = false;
+        document.editorconfig.insert_final_newline = Some(true);
+        document.editorconfig.end_of_line = Some(LineEnding::Crlf);
+        assert_eq!(written(&document), b"one\r\ntwo\r\n");
+    }
+
+    #[test]
+    fn crlf_round_trips() {
+        let document = open_bytes("crlf", b"one\r\ntwo\r\n");
+        assert!(document.line_ending() == LineEnding::Crlf);
+        assert_eq!(document.len(), 2);
+        assert_eq!(document.row(0).unwrap().as_str(), "one");
+        assert_eq!(written(&document), b"one\r\ntwo\r\n");
+    }
+
+    #[test]
+    fn bom_and_missing_final_newline_round_trip() {
+        let document = open_bytes("bom", "\u{feff}one\ntwo".as_bytes());
+        assert_eq!(document.format_description(), "LF BOM noeol");
+        assert_eq!(document.row(0).unwrap().as_str(), "one");
+        assert_eq!(written(&document), "\u{feff}one\ntwo".as_bytes());
+    }
+
+    #[test]
+    fn mixed_endings_go_by_the_majority() {
+        assert!(open_bytes("mixed-crlf", b"a\nb\r\nc\r\n").line_ending() == LineEnding::Crlf);
+        assert!(open_bytes("mixed-lf", b"a\r\nb\nc\n").line_ending() == LineEnding::Lf);
+    }
+
+    #[test]
+    fn new_lines_take_the_document_ending() {
+        let mut document = open_bytes("newline", b"one\r\n");
+        document.insert_newline(&Position { x: 3, y: 0 });
+        assert_eq!(document.contents(), "one\r\n\r\n");
+    }
+
+    #[test]
+    fn undo_switches_the_line_ending_back() {
+        let mut document = open_bytes("toggle", b"one\r\ntwo\r\n");
+        let cursor = Position { x: 1, y: 1 };
+        document.set_line_ending(LineEnding::Lf, &cursor);
+        assert_eq!(document.contents(), "one\ntwo\n");
+        assert!(document.line_ending() == LineEnding::Lf);
+
+        assert!(document.undo() == Some(cursor));
+        assert_eq!(document.contents(), "one\r\ntwo\r\n");
+        assert!(document.line_ending() == LineEnding::Crlf);
+        assert!(!document.is_dirty());
+
+        document.redo();
+        assert_eq!(document.contents(), "one\ntwo\n");
+        assert!(document.line_ending() == LineEnding::Lf);
+    }
+
+    #[test]
+    fn switching_the_ending_of_a_single_line_can_be_undone() {
+        let mut document = open_bytes("single", b"one");
+        document.set_line_ending(LineEnding::Crlf, &Position::default());
+        assert!(document.is_dirty());
+        document.undo();
+        assert!(document.line_ending() == LineEnding::Lf);
+        assert!(!document.is_dirty());
+    }
+}
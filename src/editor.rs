@@ -1,25 +1,49 @@
 use crate::terminal::Terminal;
 use crate::position::Position;
-use crate::document::Document;
+use crate::buffer::Buffer;
+use crate::clipboard::{Clipboard, Yank};
+use crate::commandline::{self, ExCommand, Setting, Substitution};
+use crate::config::Config;
+use crate::document::{Document, LineEnding};
+use crate::keymap::{self, Binding, Command, Key, Keymap};
 use crate::row::Row;
+use crate::screen::{Cell, Screen, Style};
 use crate::statusmessage::StatusMessage;
+use crate::view::{Layout, Rect, SplitDirection, View};
+use crate::vi::{self, Action, Change, Entry, Mode, Motion, Operator, Parse, Vi};
+use crate::diff;
 
 use std::env;
+use std::mem;
+use std::ops::Range;
 
 
 use std::time::{Instant, Duration};
 
-use crossterm::event::{Event, KeyCode, KeyEvent, KeyModifiers};
+use crossterm::event::{Event, KeyCode, KeyEvent, KeyModifiers, MouseButton, MouseEvent, MouseEventKind};
 
 
 
-use crossterm::style::{PrintStyledContent, Print, SetForegroundColor, SetBackgroundColor, ResetColor, Color, Attribute, Stylize};
+use regex::Regex;
 
 
 const VERSION: &str = env!("CARGO_PKG_VERSION");
-const STATUS_BG_COLOR: Color = Color::Rgb{r: 239, g: 239, b: 239};
-const STATUS_FG_COLOR: Color = Color::Rgb{r: 63, g: 63, b: 63};
-const QUIT_TIMES: u8 = 3;
+const DOUBLE_CLICK_TIME: Duration = Duration::from_millis(400);
+const WHEEL_LINES: usize = 3;
+
+#[derive(PartialEq, Clone, Copy)]
+pub enum SearchDirection {
+    Forward,
+    Backward,
+}
+
+#[derive(PartialEq, Clone, Copy)]
+pub enum LineNumbers {
+    Off,
+    Absolute,
+    // Distance from the cursor's line, which itself keeps its real number.
+    Relative,
+}
 
 
 
@@ -27,11 +51,39 @@ const QUIT_TIMES: u8 = 3;
 pub struct Editor {
     should_quit: bool,
     terminal: Terminal,
+    // The frame being drawn, and what the last one left on the terminal.
+    screen: Screen,
     cursor_position: Position,
     offset: Position,
-    document: Document,
+    anchor: Option<Position>,
+    buffers: Vec<Buffer>,
+    // The cursor, offset and anchor above belong to the active view; the others keep theirs in `views`.
+    views: Vec<View>,
+    active_view: usize,
+    layout: Layout,
     status_message: StatusMessage,
-    quit_times: u8
+    quit_times: u8,
+    highlighted: Option<Regex>,
+    last_swap: Instant,
+    clipboard: Clipboard,
+    // Register picked for the next cut, copy or paste.
+    register: Option<char>,
+    // Where the last paste from the kill ring landed, and which entry it was.
+    last_paste: Option<(Position, Position, usize)>,
+    line_numbers: LineNumbers,
+    soft_wrap: bool,
+    mouse: bool,
+    // When and where the left button last went down, to spot double clicks.
+    last_click: Option<(Instant, Position)>,
+    keymap: Keymap,
+    // Keys of a chord typed so far.
+    pending_keys: Vec<Key>,
+    // Modal editing state, when vi mode is on.
+    vi: Option<Vi>,
+    // Lines run from the command line, oldest first.
+    command_history: Vec<String>,
+    config: Config,
+    last_autosave: Instant,
 }
 
 impl Editor {
@@ -39,292 +91,1869 @@ impl Editor {
     pub fn default() -> Self {
 
         let args: Vec<String> = env::args().collect();
-        let mut initial_status = String::from("HELP: Ctrl+s == save | Ctrl+q = quit");
-        let document = if args.len() > 1 {
-            let file_name = &args[1];
-            // Document::open(&file_name).unwrap_or_default()
-            let doc = Document::open(file_name);
-
-            if doc.is_ok() {
-                doc.unwrap()
-            } else {
-                initial_status = format!("ERR: Could not open file: {}", file_name);
-                Document::default()
+        let (mut config, mut errors) = Config::load();
+        config.apply_args(&args);
+        let file_names: Vec<&String> = args.iter().skip(1).filter(|arg| !arg.starts_with("--")).collect();
+        let mut initial_status = String::from("HELP: Ctrl+s == save | Ctrl+q = quit | Ctrl+f = find | Ctrl+r = replace | Ctrl+z = undo | Ctrl+y = redo | Ctrl+o = open | Ctrl+b = buffers | Alt+s/v = split | Ctrl+x/c/v = cut/copy/paste | Ctrl+p = command");
+        let mut buffers = Vec::new();
+
+        for file_name in file_names {
+            match open_document(file_name, &config) {
+                Ok(document) => buffers.push(Buffer { document, ..Buffer::default() }),
+                Err(error) => initial_status = format!("ERR: Could not open {}: {}", file_name, error),
             }
-        } else {
-            Document::default()
-        };
+        }
 
+        if buffers.is_empty() {
+            let mut document = Document::default();
+            config.configure(&mut document);
+            buffers.push(Buffer { document, ..Buffer::default() });
+        }
+
+        let (keymap, keymap_errors) = Keymap::load();
+        errors.extend(keymap_errors);
+        if let Some(error) = errors.first() {
+            initial_status = match errors.len() {
+                1 => format!("ERR: {}", error),
+                count => format!("ERR: {} (and {} more)", error, count - 1),
+            };
+        }
+
+        let mut clipboard = Clipboard::default();
+        clipboard.osc52 = config.osc52;
+
+        let terminal = Terminal::default().expect("Failed to initialize the terminal.");
+        terminal.capture_mouse(config.mouse);
 
         Self {
             should_quit: false,
-            terminal: Terminal::default().expect("Failed to initialize the terminal."),
-            document,
+            terminal,
+            screen: Screen::default(),
+            buffers,
+            views: vec![View::default()],
+            active_view: 0,
+            layout: Layout::View(0),
             cursor_position: Position::default(),
             offset: Position::default(),
+            anchor: None,
             status_message: StatusMessage::from(initial_status),
-            quit_times: QUIT_TIMES,
-
+            quit_times: config.quit_times,
+            highlighted: None,
+            last_swap: Instant::now(),
+            clipboard,
+            register: None,
+            last_paste: None,
+            line_numbers: config.line_numbers,
+            soft_wrap: config.soft_wrap,
+            mouse: config.mouse,
+            last_click: None,
+            keymap,
+            pending_keys: Vec::new(),
+            vi: config.vi.then(Vi::default),
+            command_history: Vec::new(),
+            config,
+            last_autosave: Instant::now(),
         }
     }
 
 
     pub fn run(&mut self) {
 
-
+        for index in 0..self.buffers.len() {
+            self.switch_buffer(index);
+            self.check_swap();
+        }
+        self.switch_buffer(0);
 
         loop {
-            if let Err(error) = self.process_keypress() {
+            self.refresh_screen();
+
+            if self.should_quit {
+                break;
+            };
+
+            // Wake up in time for whichever of the swap file and autosave is due first.
+            let timeout = self
+                .config
+                .autosave_interval
+                .map_or(self.config.swap_interval, |interval| interval.min(self.config.swap_interval));
+            let has_event = self.terminal.has_event(timeout).unwrap_or(false);
+            if has_event && self.process_keypress().is_err() {
                println!("Oh no!");
 
             }
+
+            self.update_swap();
+            self.autosave();
+            self.check_disk();
+
+        }
+
+        self.shutdown();
+    }
+
+    // Journals unsaved changes every swap interval and drops the journal once they are saved.
+    fn update_swap(&mut self) {
+        if self.last_swap.elapsed() < self.config.swap_interval {
+            return;
+        }
+        self.last_swap = Instant::now();
+
+        for buffer in &mut self.buffers {
+            if buffer.document.is_dirty() {
+                if let Err(error) = buffer.document.write_swap() {
+                    self.status_message = StatusMessage::from(format!("Could not write swap file: {}", error));
+                }
+            } else if buffer.document.owns_swap() {
+                buffer.document.remove_swap();
+            }
+        }
+    }
+
+    // Writes out named buffers with unsaved changes every autosave interval, when one is set.
+    fn autosave(&mut self) {
+        let Some(interval) = self.config.autosave_interval else {
+            return;
+        };
+        if self.last_autosave.elapsed() < interval {
+            return;
+        }
+        self.last_autosave = Instant::now();
+
+        for buffer in &mut self.buffers {
+            let document = &mut buffer.document;
+            if !document.is_dirty() || document.file_name.is_none() || document.changed_on_disk() {
+                continue;
+            }
+            if let Err(error) = document.save() {
+                self.status_message = StatusMessage::from(format!("Could not autosave {}: {}", buffer.name(), error));
+            }
+        }
+    }
+
+    // Rereads the config files and applies them to the editor and every open buffer.
+    fn reload_config(&mut self) {
+        let (mut config, errors) = Config::load();
+        config.apply_args(&env::args().collect::<Vec<String>>());
+
+        for buffer in &mut self.buffers {
+            config.configure(&mut buffer.document);
+        }
+        self.clipboard.osc52 = config.osc52;
+        if self.mouse != config.mouse {
+            self.mouse = config.mouse;
+            self.terminal.capture_mouse(self.mouse);
+        }
+        self.line_numbers = config.line_numbers;
+        self.quit_times = config.quit_times;
+        if self.vi.is_some() != config.vi {
+            self.vi = config.vi.then(Vi::default);
+            self.anchor = None;
+        }
+        let soft_wrap = config.soft_wrap;
+        self.config = config;
+        self.set_soft_wrap(soft_wrap);
+
+        self.status_message = StatusMessage::from(match errors.first() {
+            None => "Config reloaded.".to_string(),
+            Some(error) if errors.len() == 1 => format!("ERR: {}", error),
+            Some(error) => format!("ERR: {} (and {} more)", error, errors.len() - 1),
+        });
+    }
+
+    fn current_buffer(&self) -> usize {
+        self.views[self.active_view].buffer
+    }
+
+    fn document(&self) -> &Document {
+        &self.buffers[self.current_buffer()].document
+    }
+
+    fn document_mut(&mut self) -> &mut Document {
+        let current_buffer = self.current_buffer();
+        &mut self.buffers[current_buffer].document
+    }
+
+    // Parks the cursor of the current buffer and brings up the one at `index` in the active view.
+    fn switch_buffer(&mut self, index: usize) {
+        if index >= self.buffers.len() {
+            return;
+        }
+
+        let current_buffer = self.current_buffer();
+        let current = &mut self.buffers[current_buffer];
+        current.cursor_position = self.cursor_position;
+        current.offset = self.offset;
+        self.document_mut().break_undo_group();
+
+        self.views[self.active_view].buffer = index;
+        self.cursor_position = self.buffers[index].cursor_position;
+        self.offset = self.buffers[index].offset;
+        self.anchor = None;
+        self.clamp_cursor();
+    }
+
+    fn cycle_buffer(&mut self, forward: bool) {
+        let len = self.buffers.len();
+        let current_buffer = self.current_buffer();
+        let index = if forward {
+            (current_buffer + 1) % len
+        } else {
+            (current_buffer + len - 1) % len
+        };
+
+        self.switch_buffer(index);
+        self.status_message = StatusMessage::from(format!(
+            "Buffer {}/{}: {}", index + 1, len, self.buffers[index].name()
+        ));
+    }
+
+    fn open_file(&mut self) {
+        let file_name = self
+            .prompt("Open: ", |_, _, _| {})
+            .unwrap_or(None)
+            .filter(|file_name| !file_name.is_empty());
+
+        let Some(file_name) = file_name else {
+            self.status_message = StatusMessage::from("Open aborted.".to_string());
+            return;
+        };
+        self.open_path(file_name);
+    }
+
+    // Switches to the buffer holding `file_name`, opening it first if need be.
+    fn open_path(&mut self, file_name: String) {
+        if let Some(index) = self.buffers.iter().position(|buffer| buffer.document.file_name.as_ref() == Some(&file_name)) {
+            self.switch_buffer(index);
+            return;
+        }
+
+        let document = match open_document(&file_name, &self.config) {
+            Ok(document) => document,
+            Err(error) => {
+                self.status_message = StatusMessage::from(format!("Could not open {}: {}", file_name, error));
+                return;
+            },
+        };
+
+        self.buffers.push(Buffer { document, ..Buffer::default() });
+        self.switch_buffer(self.buffers.len() - 1);
+        self.check_swap();
+    }
+
+    fn close_buffer(&mut self) {
+        if self.document().is_dirty() {
+            let key = self.ask("Buffer has unsaved changes! Close anyway? (y/n)");
+            if !matches!(key, Ok(KeyEvent { code: KeyCode::Char('y'), .. })) {
+                return;
+            }
+        }
+
+        if self.document().owns_swap() {
+            self.document_mut().remove_swap();
+        }
+
+        let current_buffer = self.current_buffer();
+        let closed = self.buffers.remove(current_buffer);
+        if self.buffers.is_empty() {
+            let mut document = Document::default();
+            self.config.configure(&mut document);
+            self.buffers.push(Buffer { document, ..Buffer::default() });
+        }
+
+        // Views showing the closed buffer fall back to its neighbour; the rest follow their buffer's new index.
+        let fallback = current_buffer.min(self.buffers.len() - 1);
+        for view in &mut self.views {
+            if view.buffer == current_buffer {
+                view.buffer = fallback;
+                view.cursor_position = self.buffers[fallback].cursor_position;
+                view.offset = self.buffers[fallback].offset;
+                view.anchor = None;
+            } else if view.buffer > current_buffer {
+                view.buffer -= 1;
+            }
+        }
+
+        self.load_view();
+        self.status_message = StatusMessage::from(format!("Closed {}", closed.name()));
+    }
+
+    // Lists every buffer and switches to the one picked by number or by name.
+    fn choose_buffer(&mut self) {
+        let list: Vec<String> = self
+            .buffers
+            .iter()
+            .enumerate()
+            .map(|(index, buffer)| {
+                let marker = if index == self.current_buffer() { ">" } else { "" };
+                let dirty = if buffer.document.is_dirty() { "+" } else { "" };
+                format!("{}{}:{}{}", marker, index + 1, buffer.name(), dirty)
+            })
+            .collect();
+
+        let choice = self
+            .prompt(&format!("{} | Buffer: ", list.join(" ")), |_, _, _| {})
+            .unwrap_or(None)
+            .filter(|choice| !choice.is_empty());
+
+        let Some(choice) = choice else {
+            return;
+        };
+
+        let index = match choice.parse::<usize>() {
+            Ok(number) => number.checked_sub(1),
+            Err(_) => self.buffers.iter().position(|buffer| buffer.name().contains(&choice)),
+        };
+
+        match index {
+            Some(index) if index < self.buffers.len() => self.switch_buffer(index),
+            _ => self.status_message = StatusMessage::from(format!("No buffer matches {}", choice)),
+        }
+    }
+
+    // Someone else rewrote the file: reload it, or let the user pick a side when both changed.
+    // The current buffer is asked about right away. A buffer in the background is pointed out
+    // once, and asked about as soon as it is shown.
+    fn check_disk(&mut self) {
+        let current_buffer = self.current_buffer();
+        for (index, buffer) in self.buffers.iter_mut().enumerate() {
+            if index == current_buffer || buffer.disk_change_noticed || !buffer.document.changed_on_disk() {
+                continue;
+            }
+            buffer.disk_change_noticed = true;
+            self.status_message = StatusMessage::from(format!("{} changed on disk.", buffer.name()));
+        }
+
+        if !self.document_mut().changed_on_disk() {
+            return;
+        }
+        self.buffers[current_buffer].disk_change_noticed = false;
+
+        loop {
+            let question = if self.document().is_dirty() {
+                "File changed on disk! Keep (m)ine, load (t)heirs, or (d)iff?"
+            } else {
+                "File changed on disk. (r)eload or (i)gnore?"
+            };
+            let key = match self.ask(question) {
+                Ok(key) => key,
+                Err(_) => return,
+            };
+
+            match key.code {
+                KeyCode::Char('m' | 'i') => {
+                    self.document_mut().acknowledge_disk_change();
+                    self.status_message = StatusMessage::from("Keeping the buffer; saving will overwrite the file.".to_string());
+                    break;
+                },
+                KeyCode::Char('t' | 'r') => {
+                    self.reload();
+                    break;
+                },
+                KeyCode::Char('d') => {
+                    let theirs = self
+                        .document()
+                        .file_name
+                        .as_ref()
+                        .and_then(|file_name| Document::open(file_name).ok())
+                        .map(|document| document.contents())
+                        .unwrap_or_default();
+                    self.show_diff("Changes on disk", &theirs);
+                },
+                _ => (),
+            }
+        }
+    }
+
+    fn reload(&mut self) {
+        match self.document_mut().reload() {
+            Ok(()) => {
+                self.status_message = StatusMessage::from("Reloaded from disk.".to_string());
+            },
+            Err(error) => {
+                self.status_message = StatusMessage::from(format!("Could not reload file: {}", error));
+                return;
+            },
+        }
+
+        self.clamp_cursor();
+    }
+
+    // Edits made through another view may have pulled the text out from under the cursor.
+    fn clamp_cursor(&mut self) {
+        self.cursor_position = self.clamp(self.cursor_position);
+        self.anchor = self.anchor.map(|anchor| self.clamp(anchor));
+        self.scroll();
+    }
+
+    fn clamp(&self, position: Position) -> Position {
+        let y = position.y.min(self.document().len());
+        let x = position.x.min(self.document().row(y).map_or(0, |row| row.len()));
+        Position { x, y }
+    }
+
+    fn split_view(&mut self, direction: SplitDirection) {
+        self.views.push(self.view_state(self.active_view));
+
+        let new_view = self.views.len() - 1;
+        self.layout.split(self.active_view, new_view, direction);
+        self.focus_view(new_view);
+    }
+
+    fn close_view(&mut self) {
+        if self.views.len() == 1 {
+            self.status_message = StatusMessage::from("Cannot close the last view.".to_string());
+            return;
+        }
+
+        self.document_mut().break_undo_group();
+        self.layout.remove(self.active_view);
+        self.views.remove(self.active_view);
+
+        // Focus whatever now fills the first slot on screen.
+        self.active_view = self.view_rects().first().map_or(0, |(view, _)| *view);
+        self.load_view();
+    }
+
+    // Moves focus to the next view in screen order.
+    fn cycle_view(&mut self) {
+        let rects = self.view_rects();
+        let position = rects.iter().position(|(view, _)| *view == self.active_view).unwrap_or(0);
+        let (next, _) = rects[(position + 1) % rects.len()];
+        self.focus_view(next);
+    }
+
+    fn focus_view(&mut self, index: usize) {
+        self.document_mut().break_undo_group();
+        self.views[self.active_view] = self.view_state(self.active_view);
+
+        self.active_view = index;
+        self.load_view();
+    }
+
+    // Takes over the cursor, offset and anchor the active view had when it lost focus.
+    fn load_view(&mut self) {
+        let view = self.views[self.active_view];
+        self.cursor_position = view.cursor_position;
+        self.offset = view.offset;
+        self.anchor = view.anchor;
+        self.clamp_cursor();
+    }
+
+    // Every view's area on screen, leaving the bottom line for the message bar.
+    fn view_rects(&self) -> Vec<(usize, Rect)> {
+        let size = self.terminal.size();
+        let area = Rect {
+            x: 0,
+            y: 0,
+            width: size.columns as usize,
+            height: (size.rows as usize).saturating_sub(1),
+        };
+
+        let mut rects = Vec::new();
+        self.layout.rects(area, &mut rects);
+        rects
+    }
+
+    // Width and height of the text area of the active view.
+    fn view_size(&self) -> (usize, usize) {
+        let gutter = self.gutter_width(self.document());
+        self.view_rects()
+            .into_iter()
+            .find(|(view, _)| *view == self.active_view)
+            .map_or((0, 0), |(_, rect)| (rect.width.saturating_sub(gutter), rect.height.saturating_sub(1)))
+    }
+
+    // Columns in front of the text taken up by line numbers, wide enough for the last line.
+    fn gutter_width(&self, document: &Document) -> usize {
+        if self.line_numbers == LineNumbers::Off {
+            0
+        } else {
+            document.len().max(1).to_string().len() + 1
+        }
+    }
+
+    fn cycle_line_numbers(&mut self) {
+        let (line_numbers, name) = match self.line_numbers {
+            LineNumbers::Off => (LineNumbers::Absolute, "on"),
+            LineNumbers::Absolute => (LineNumbers::Relative, "relative"),
+            LineNumbers::Relative => (LineNumbers::Off, "off"),
+        };
+
+        self.line_numbers = line_numbers;
+        self.status_message = StatusMessage::from(format!("Line numbers {}", name));
+    }
+
+    fn view_state(&self, view: usize) -> View {
+        if view == self.active_view {
+            View {
+                buffer: self.current_buffer(),
+                cursor_position: self.cursor_position,
+                offset: self.offset,
+                anchor: self.anchor,
+            }
+        } else {
+            self.views[view]
+        }
+    }
+
+    // In vi's Visual mode the selection always takes in the grapheme under the cursor.
+    fn selection(&self) -> Option<(Position, Position)> {
+        if self.mode() != Some(Mode::Visual) {
+            return self.view_state(self.active_view).selection();
+        }
+
+        let anchor = self.anchor?;
+        let cursor = self.cursor_position;
+        let (start, end) = if (anchor.y, anchor.x) < (cursor.y, cursor.x) { (anchor, cursor) } else { (cursor, anchor) };
+        let len = self.document().row(end.y).map_or(0, |row| row.len());
+        Some((start, Position { x: (end.x + 1).min(len), y: end.y }))
+    }
+
+    // Removes the selected text, leaving the cursor where it began.
+    fn delete_selection(&mut self) {
+        if let Some((start, end)) = self.selection() {
+            self.cursor_position = self.document_mut().delete_range(&start, &end);
+        }
+        self.anchor = None;
+    }
+
+    // Runs `edit` in place of the selection, so undo brings back the selected text along with it.
+    fn replace_selection<F: FnOnce(&mut Self)>(&mut self, edit: F) {
+        if self.selection().is_none() {
+            self.anchor = None;
+            edit(self);
+            return;
+        }
+
+        let cursor_position = self.cursor_position;
+        self.document_mut().begin_undo_group(&cursor_position);
+        self.delete_selection();
+        edit(self);
+        self.document_mut().end_undo_group();
+    }
+
+    // Copies the selection, or the cursor's whole line when nothing is selected, removing it if `cut`.
+    fn copy(&mut self, register: Option<char>, cut: bool) {
+        let (start, end, linewise) = if let Some((start, end)) = self.selection() {
+            (start, end, false)
+        } else {
+            let y = self.cursor_position.y;
+            if y >= self.document().len() {
+                self.status_message = StatusMessage::from("Nothing to copy.".to_string());
+                return;
+            }
+            (Position { x: 0, y }, Position { x: 0, y: y + 1 }, true)
+        };
+
+        let mut text = self.document().text_range(&start, &end);
+        if linewise && !text.ends_with('\n') {
+            text.push('\n');
+        }
+        let message = format!("{} {} character(s)", if cut { "Cut" } else { "Copied" }, text.chars().count());
+        self.clipboard.yank(register, Yank { text, linewise });
+
+        if cut {
+            self.document_mut().break_undo_group();
+            self.cursor_position = self.document_mut().delete_range(&start, &end);
+            self.anchor = None;
+        }
+        self.status_message = StatusMessage::from(message);
+    }
+
+    fn paste(&mut self, register: Option<char>) {
+        let Some(yank) = self.clipboard.get(register, 0).cloned() else {
+            self.status_message = StatusMessage::from("Nothing to paste.".to_string());
+            return;
+        };
+
+        let (start, end) = self.insert_yank(&yank);
+        if register.is_none() {
+            self.last_paste = Some((start, end, 0));
+        }
+    }
+
+    // Swaps the text just pasted for the next older entry in the kill ring.
+    fn cycle_paste(&mut self, last_paste: Option<(Position, Position, usize)>) {
+        let Some((start, end, index)) = last_paste else {
+            self.status_message = StatusMessage::from("Cycling only works right after a paste.".to_string());
+            return;
+        };
+
+        let index = (index + 1) % self.clipboard.ring_len().max(1);
+        let Some(yank) = self.clipboard.get(None, index).cloned() else {
+            return;
+        };
+
+        let cursor_position = self.cursor_position;
+        self.document_mut().begin_undo_group(&cursor_position);
+        self.document_mut().delete_range(&start, &end);
+        self.cursor_position = start;
+        self.anchor = None;
+        let (start, end) = self.insert_yank(&yank);
+        self.document_mut().end_undo_group();
+
+        self.last_paste = Some((start, end, index));
+        self.status_message = StatusMessage::from(format!("Kill ring entry {}/{}", index + 1, self.clipboard.ring_len()));
+    }
+
+    // Puts `yank` in place of the selection, or above the cursor's line if it holds whole lines.
+    fn insert_yank(&mut self, yank: &Yank) -> (Position, Position) {
+        let mut pasted = (self.cursor_position, self.cursor_position);
+        self.document_mut().break_undo_group();
+
+        let linewise = yank.linewise && self.selection().is_none();
+        self.replace_selection(|editor| {
+            let Position { x, y } = editor.cursor_position;
+            let at = if linewise { Position { x: 0, y } } else { editor.cursor_position };
+            let end = editor.document_mut().insert_str(&at, &yank.text);
+
+            editor.cursor_position = if linewise { Position { x, y: end.y } } else { end };
+            pasted = (at, end);
+        });
+
+        pasted
+    }
+
+    // A swap file left behind by a crashed session holds edits that never reached the disk.
+    fn check_swap(&mut self) {
+        if !self.document().has_swap() {
+            return;
+        }
+        if let Some(owner) = self.document().swap_in_use() {
+            self.status_message = StatusMessage::from(format!(
+                "ERR: {} is also open in {}; changes here won't be journaled",
+                self.buffers[self.current_buffer()].name(),
+                owner
+            ));
+            return;
+        }
+
+        let recovered = match self.document().read_swap() {
+            Ok(recovered) => recovered,
+            Err(error) => {
+                self.status_message = StatusMessage::from(format!("Could not read swap file: {}", error));
+                return;
+            },
+        };
+
+        loop {
+            let key = match self.ask("Swap file found! (r)ecover, (d)iff, or (x) discard it?") {
+                Ok(key) => key,
+                Err(_) => return,
+            };
+
+            match key.code {
+                KeyCode::Char('r') => {
+                    self.document_mut().replace_contents(&recovered);
+                    self.status_message = StatusMessage::from("Recovered unsaved changes from swap file.".to_string());
+                    break;
+                },
+                KeyCode::Char('d') => self.show_diff("Swap file changes", &recovered),
+                KeyCode::Char('x') => {
+                    self.document_mut().remove_swap();
+                    self.status_message = StatusMessage::from("Swap file discarded.".to_string());
+                    break;
+                },
+                _ => (),
+            }
+        }
+    }
+
+    // Temporarily shows how `other` differs from the document until a non-scrolling key is hit.
+    fn show_diff(&mut self, title: &str, other: &str) {
+        let mut diff = Document::from_text(&diff::lines(&self.document().contents(), other));
+        diff.tab_width = self.document().tab_width;
+        let document = mem::replace(self.document_mut(), diff);
+        let cursor_position = mem::take(&mut self.cursor_position);
+        let offset = mem::take(&mut self.offset);
+        let anchor = self.anchor.take();
+
+        let question = format!("{} (arrows scroll, any other key returns)", title);
+        while let Ok(key) = self.ask(&question) {
+            match key.code {
+                KeyCode::Up | KeyCode::Down | KeyCode::PageUp | KeyCode::PageDown => {
+                    self.move_cursor_by_key(key.code);
+                    self.scroll();
+                },
+                _ => break,
+            }
+        }
+
+        *self.document_mut() = document;
+        self.cursor_position = cursor_position;
+        self.offset = offset;
+        self.anchor = anchor;
+    }
+
+    // Shows `question` in the message bar and waits for a single key press.
+    fn ask(&mut self, question: &str) -> Result<KeyEvent, std::io::Error> {
+        self.status_message = StatusMessage::from(question.to_string());
+
+        loop {
             self.refresh_screen();
+            if let Event::Key(key) = self.terminal.read_key()? {
+                return Ok(key);
+            }
+        }
+    }
 
-            if self.should_quit {
+    fn refresh_screen(&mut self) {
+        let rects = self.view_rects();
+        for (view, rect) in &rects {
+            let View { buffer, offset, .. } = self.view_state(*view);
+            self.buffers[buffer].document.highlight(offset.y.saturating_add(rect.height));
+        }
+
+        // Everything is drawn into the back-buffer; only what differs from the last frame goes out.
+        let mut screen = mem::take(&mut self.screen);
+        let size = self.terminal.size();
+        screen.begin(size.columns as usize, size.rows as usize);
+
+        let mut cursor = None;
+        if self.should_quit {
+            screen.put_str(0, 0, "Good bye!", Style::default());
+        } else if self.terminal.is_too_small() {
+            screen.put_str(0, 0, "Window too small", Style::default());
+        } else {
+            for (view, rect) in &rects {
+                self.draw_view(&mut screen, *view, rect);
+            }
+            self.draw_message_bar(&mut screen);
+
+            let active = rects.iter().find(|(view, _)| *view == self.active_view);
+            if let (Some((_, rect)), Some(on_screen)) = (active, self.cursor_on_screen()) {
+                cursor = Some(Position {
+                    x: rect.x + self.gutter_width(self.document()) + on_screen.x,
+                    y: rect.y + on_screen.y,
+                });
+            }
+        }
+
+        if let Ok(frame) = screen.diff(cursor) {
+            self.terminal.write(&frame);
+        }
+        self.screen = screen;
+    }
+
+    // Whether the document made it to disk.
+    fn save(&mut self) -> bool {
+        if self.document().file_name.is_none() {
+            let new_name = self
+                .prompt("Save as: ", |_, _, _| {})
+                .unwrap_or(None)
+                .filter(|name| !name.is_empty());
+
+            let Some(new_name) = new_name else {
+                self.status_message = StatusMessage::from("Save aborted!".to_string());
+                return false;
+            };
+            return self.save_as(&new_name);
+        }
+
+        if self.document_mut().changed_on_disk() {
+            let key = self.ask("File changed on disk since it was read! (o)verwrite or (c)ancel?");
+            if !matches!(key, Ok(KeyEvent { code: KeyCode::Char('o'), .. })) {
+                self.status_message = StatusMessage::from("Save cancelled; the file on disk was left alone.".to_string());
+                return false;
+            }
+        }
+
+        match self.document_mut().save() {
+            Ok(()) => {
+                self.status_message = StatusMessage::from("File saved successfully".to_string());
+                true
+            },
+            Err(error) => {
+                self.status_message = StatusMessage::from(format!("Error writing file: {}", error));
+                false
+            },
+        }
+    }
+
+    // The buffer takes the new name, and the settings that go with it, only once it is written.
+    fn save_as(&mut self, file_name: &str) -> bool {
+        let buffer = self.current_buffer();
+        let document = &mut self.buffers[buffer].document;
+        match document.save_as(file_name) {
+            Ok(()) => {
+                self.config.configure(document);
+                self.status_message = StatusMessage::from("File saved successfully".to_string());
+                true
+            },
+            Err(error) => {
+                self.status_message = StatusMessage::from(format!("Error writing file: {}", error));
+                false
+            },
+        }
+    }
+
+    fn search(&mut self) {
+        let old_position = self.cursor_position;
+        let old_offset = self.offset;
+        let mut direction = SearchDirection::Forward;
+        self.anchor = None;
+
+        let query = self
+            .prompt(
+                "Search (ESC to cancel, Arrows to navigate): ",
+                |editor, key, query| {
+                    let mut moved = false;
+                    match key.map(|key| key.code) {
+                        Some(KeyCode::Right | KeyCode::Down) => {
+                            direction = SearchDirection::Forward;
+                            editor.move_cursor_by_key(KeyCode::Right);
+                            moved = true;
+                        },
+                        Some(KeyCode::Left | KeyCode::Up) => direction = SearchDirection::Backward,
+                        _ => direction = SearchDirection::Forward,
+                    }
+
+                    if let Some(position) = editor.document().find(query, &editor.cursor_position, direction) {
+                        editor.cursor_position = position;
+                        editor.scroll();
+                    } else if moved {
+                        editor.move_cursor_by_key(KeyCode::Left);
+                    }
+
+                    editor.highlighted = Regex::new(&regex::escape(query)).ok();
+                },
+            )
+            .unwrap_or(None)
+            .filter(|query| !query.is_empty());
+
+        if query.is_none() {
+            self.cursor_position = old_position;
+            self.offset = old_offset;
+            self.scroll();
+        }
+
+        self.highlighted = None;
+    }
+
+    // Regex search and replace, asking about each match unless `replace_all` is set.
+    fn replace(&mut self, mut replace_all: bool) {
+        let pattern = self
+            .prompt("Replace (regex): ", |_, _, _| {})
+            .unwrap_or(None)
+            .filter(|pattern| !pattern.is_empty());
+
+        let regex = match pattern.map(|pattern| Regex::new(&pattern)) {
+            Some(Ok(regex)) => regex,
+            Some(Err(error)) => {
+                self.status_message = StatusMessage::from(format!("Invalid pattern: {}", error));
+                return;
+            },
+            None => {
+                self.status_message = StatusMessage::from("Replace aborted.".to_string());
+                return;
+            },
+        };
+
+        let replacement = if let Ok(Some(replacement)) = self.prompt("Replace with: ", |_, _, _| {}) {
+            replacement
+        } else {
+            self.status_message = StatusMessage::from("Replace aborted.".to_string());
+            return;
+        };
+
+        let mut at = if replace_all {
+            Position::default()
+        } else {
+            self.cursor_position
+        };
+        let mut replaced = 0;
+
+        self.anchor = None;
+        self.highlighted = Some(regex.clone());
+        let cursor_position = self.cursor_position;
+        self.document_mut().begin_undo_group(&cursor_position);
+
+        while let Some(found) = self.document().find_regex(&regex, &at, &replacement) {
+            if !replace_all {
+                self.cursor_position = found.start;
+                self.scroll();
+                let key = match self.ask("Replace this match? (y)es (n)o (a)ll (q)uit") {
+                    Ok(key) => key,
+                    Err(_) => break,
+                };
+
+                match key.code {
+                    KeyCode::Char('y') => (),
+                    KeyCode::Char('a') => replace_all = true,
+                    KeyCode::Char('n') => {
+                        at = if found.start == found.end {
+                            self.document().next_position(&found.end)
+                        } else {
+                            found.end
+                        };
+                        continue;
+                    },
+                    KeyCode::Char('q') | KeyCode::Esc => break,
+                    _ => continue,
+                }
+            }
+
+            at = self.document_mut().replace(&found);
+            if found.start == found.end {
+                at = self.document().next_position(&at);
+            }
+            self.cursor_position = found.start;
+            replaced += 1;
+        }
+
+        self.document_mut().end_undo_group();
+        self.highlighted = None;
+        self.scroll();
+        self.status_message = StatusMessage::from(format!("Replaced {} occurrence(s).", replaced));
+    }
+
+    fn toggle_line_ending(&mut self) {
+        let line_ending = match self.document().line_ending() {
+            LineEnding::Lf => LineEnding::Crlf,
+            LineEnding::Crlf => LineEnding::Lf,
+        };
+
+        let cursor_position = self.cursor_position;
+        self.document_mut().set_line_ending(line_ending, &cursor_position);
+        self.status_message = StatusMessage::from(format!("Line endings set to {}", line_ending.name()));
+    }
+
+    fn draw_status_bar(&self, screen: &mut Screen, view: usize, rect: &Rect) {
+        // let spaces = " ".repeat(self.terminal.size().columns as usize);
+
+        let mut status;
+        let width = rect.width;
+        let View { buffer, cursor_position, .. } = self.view_state(view);
+        let document = &self.buffers[buffer].document;
+        let modified_indicator = if document.is_dirty() {
+            " (modified)"
+        } else {
+            ""
+        };
+
+        let mut file_name = "[No Name]".to_string();
+        if let Some(name) = &document.file_name {
+            file_name = name.clone();
+            file_name.truncate(20);
+
+        }
+
+
+        status = format!("{} - {} lines {}", file_name, document.len(), modified_indicator);
+        if self.buffers.len() > 1 {
+            status = format!("[{}/{}] {}", buffer + 1, self.buffers.len(), status);
+        }
+
+
+
+        let mode = match &self.vi {
+            Some(vi) if view == self.active_view => format!("{} | ", vi.status()),
+            _ => String::new(),
+        };
+        let line_indicator = format!("{}{}{} | {} | {}/{}",
+                                     mode,
+                                     if self.soft_wrap { "wrap | " } else { "" },
+                                     document.file_type(),
+                                     document.format_description(),
+                                     cursor_position.y.saturating_add(1),
+                                     document.len(),
+                                    );
+
+        let len = status.len() + line_indicator.len();
+        if width > len {
+            status.push_str(&" ".repeat(width - len));
+        }
+
+        status = format!("{}{}", status, line_indicator);
+
+        status.truncate(width);
+
+        let (bg, fg) = if view == self.active_view {
+            (self.config.colors.status_bg, self.config.colors.status_fg)
+        } else {
+            (self.config.colors.inactive_status_bg, self.config.colors.inactive_status_fg)
+        };
+        let style = Style { fg, bg, reverse: false };
+        let y = rect.y + rect.height - 1;
+        let columns = screen.put_str(rect.x, y, &status, style);
+        screen.put_cells(rect.x + columns, y, vec![Cell::from(" ", style); width.saturating_sub(columns)]);
+    }
+
+    fn draw_message_bar(&self, screen: &mut Screen) {
+        let y = (self.terminal.size().rows as usize).saturating_sub(1);
+        let message = &self.status_message;
+        if Instant::now() - message.time < self.config.message_timeout {
+            screen.put_str(0, y, &message.text, Style::default());
+        }
+
+    }
+
+    pub fn shutdown(&self) {
+        self.terminal.shutdown();
+    }
+
+    fn draw_welcome_message(&self, screen: &mut Screen, x: usize, y: usize, width: usize) {
+        let mut welcome_message = format!("Hecto Editor -- version({})", VERSION);
+        let len = welcome_message.len();
+        let padding = width.saturating_sub(len) / 2;
+        let spaces = " ".repeat(padding.saturating_sub(1));
+
+        welcome_message = format!("~{}{}", spaces, welcome_message);
+        welcome_message.truncate(width);
+        screen.put_str(x, y, &welcome_message, Style::default());
+
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn draw_row(
+        &self,
+        screen: &mut Screen,
+        at: Position,
+        row: &Row,
+        start: usize,
+        first_column: usize,
+        width: usize,
+        tab_width: usize,
+        selected: Option<Range<usize>>,
+    ) {
+        let cells = row.render(start, first_column, width, tab_width, selected);
+        screen.put_cells(at.x, at.y, cells);
+    }
+
+    fn draw_line_number(&self, screen: &mut Screen, at: Position, y: usize, cursor_y: usize, gutter: usize) {
+        if gutter == 0 {
+            return;
+        }
+
+        let (number, color) = match self.line_numbers {
+            _ if y == cursor_y => (y + 1, self.config.colors.current_line_number),
+            LineNumbers::Relative => (y.abs_diff(cursor_y), self.config.colors.line_number),
+            _ => (y + 1, self.config.colors.line_number),
+        };
+
+        let mut number = format!("{:>width$} ", number, width = gutter - 1);
+        number.truncate(gutter);
+        screen.put_str(at.x, at.y, &number, Style { fg: color, ..Style::default() });
+    }
+
+    // Draws one view's rows and status bar, plus a separator on its right when it has a neighbour there.
+    fn draw_view(&self, screen: &mut Screen, view: usize, rect: &Rect) {
+        let state = self.view_state(view);
+        let offset = state.offset;
+        let selection = if view == self.active_view { self.selection() } else { state.selection() };
+        let document = &self.buffers[state.buffer].document;
+        let highlighted = if view == self.active_view {
+            self.highlighted.as_ref()
+        } else {
+            None
+        };
+
+        let height = rect.height.saturating_sub(1);
+        let is_empty = document.is_empty();
+        let gutter = self.gutter_width(document).min(rect.width);
+        let width = rect.width - gutter;
+
+        // Without soft wrap every row is a single screen line, shown from `offset.x` columns in.
+        let mut terminal_row = 0;
+        let mut y = offset.y;
+        let mut segment = if self.soft_wrap { offset.x } else { 0 };
+
+        while terminal_row < height {
+            let Some(row) = document.highlighted_row(y, highlighted) else {
                 break;
             };
 
+            // Rows inside the selection are selected through their line break.
+            let selected = selection
+                .filter(|(start, end)| start.y <= y && y <= end.y)
+                .map(|(start, end)| {
+                    let from = if y == start.y { start.x } else { 0 };
+                    let to = if y == end.y { end.x } else { row.len() + 1 };
+                    from..to
+                });
+            let (points, first_column) = if self.soft_wrap {
+                (row.wrap_points(width, document.tab_width), 0)
+            } else {
+                (vec![0], offset.x)
+            };
 
+            for (index, start) in points.iter().enumerate().skip(segment) {
+                if terminal_row >= height {
+                    break;
+                }
+
+                let at = Position { x: rect.x, y: rect.y + terminal_row };
+                if index == 0 {
+                    self.draw_line_number(screen, at, y, state.cursor_position.y, gutter);
+                }
+                let at = Position { x: rect.x + gutter, ..at };
+                self.draw_row(screen, at, &row, *start, first_column, width, document.tab_width, selected.clone());
+                terminal_row += 1;
+            }
+
+            segment = 0;
+            y += 1;
+        }
+
+        for terminal_row in terminal_row..height {
+            if is_empty && terminal_row == height / 3 {
+                self.draw_welcome_message(screen, rect.x, rect.y + terminal_row, rect.width);
+            } else if rect.width > 0 {
+                screen.put_str(rect.x, rect.y + terminal_row, "~", Style::default());
+            }
+        }
+
+        if rect.height > 0 {
+            self.draw_status_bar(screen, view, rect);
+        }
+
+        if rect.x + rect.width < self.terminal.size().columns as usize {
+            for terminal_row in 0..rect.height {
+                screen.put_str(rect.x + rect.width, rect.y + terminal_row, "│", Style::default());
+            }
+        }
+    }
+
+    pub fn process_keypress(&mut self) -> Result<(), std::io::Error> {
+        let event: Event = self.terminal.read_key().unwrap();
+        // A register choice or a paste only carries over to the very next key.
+        let register = self.register.take();
+        let last_paste = self.last_paste.take();
+
+        if let Event::Resize(..) = event {
+            self.scroll();
+        }
+
+        if let Event::Mouse(mouse) = event {
+            self.handle_mouse(mouse);
+            self.vi_clamp();
+            return Ok(());
+        }
+
+        // Bracketed paste hands over the whole paste at once, so it goes in as one edit.
+        if let Event::Paste(text) = &event {
+            self.document_mut().break_undo_group();
+            self.replace_selection(|editor| {
+                let cursor_position = editor.cursor_position;
+                editor.cursor_position = editor.document_mut().insert_str(&cursor_position, text);
+            });
+        }
+
+        if let Event::Key(pressed_key) = event {
+            if !self.vi_keypress(pressed_key, register) {
+                self.pending_keys.push(keymap::key_of(pressed_key));
+
+                match self.keymap.lookup(&self.pending_keys) {
+                    Binding::Command(command) => {
+                        if self.pending_keys.len() > 1 {
+                            self.status_message = StatusMessage::from(String::new());
+                        }
+                        self.pending_keys.clear();
+                        self.run_command(command, register, last_paste);
+                        // A warning about unsaved changes has to survive until the next Ctrl+Q.
+                        if command == Command::Quit {
+                            return Ok(());
+                        }
+                    },
+                    // Halfway through a chord the register choice and last paste still count.
+                    Binding::Prefix => {
+                        self.register = register;
+                        self.last_paste = last_paste;
+                        let keys: Vec<String> = self.pending_keys.iter().map(|key| keymap::key_name(*key)).collect();
+                        self.status_message = StatusMessage::from(format!("{} -", keys.join(" ")));
+                        return Ok(());
+                    },
+                    Binding::Unbound if self.pending_keys.len() > 1 => {
+                        let keys: Vec<String> = self.pending_keys.drain(..).map(keymap::key_name).collect();
+                        self.status_message = StatusMessage::from(format!("{} is not bound", keys.join(" ")));
+                    },
+                    Binding::Unbound => {
+                        self.pending_keys.clear();
+                        if let Some(vi) = &mut self.vi {
+                            vi.record(pressed_key);
+                        }
+                        self.edit(pressed_key);
+                    },
+                }
+            }
+        }
+
+        self.vi_clamp();
+        self.scroll();
+        if self.quit_times < self.config.quit_times {
+            self.quit_times = self.config.quit_times;
+            self.status_message = StatusMessage::from(String::new());
+        }
+        Ok(())
+    }
+
+    fn run_command(&mut self, command: Command, register: Option<char>, last_paste: Option<(Position, Position, usize)>) {
+        match command {
+            Command::Quit => {
+                let dirty_buffers = self.buffers.iter().filter(|buffer| buffer.document.is_dirty()).count();
+                if self.quit_times > 0 && dirty_buffers > 0 {
+                    self.status_message = StatusMessage::from(format!(
+                       "WARNING! {} buffer(s) have unsaved changes. Press ctrl+q {} more times to quit.",
+                        dirty_buffers,
+                        self.quit_times
+                    ));
+                    self.quit_times -= 1;
+                    return;
+                }
+                self.quit();
+            },
+            Command::Save => {
+                self.save();
+            },
+            Command::Find => self.search(),
+            Command::Replace => self.replace(false),
+            Command::ReplaceAll => self.replace(true),
+            Command::ToggleLineEnding => self.toggle_line_ending(),
+            Command::Open => self.open_file(),
+            Command::CloseBuffer => self.close_buffer(),
+            Command::ChooseBuffer => self.choose_buffer(),
+            Command::NextBuffer => self.cycle_buffer(true),
+            Command::PreviousBuffer => self.cycle_buffer(false),
+            Command::SplitHorizontal => self.split_view(SplitDirection::Horizontal),
+            Command::SplitVertical => self.split_view(SplitDirection::Vertical),
+            Command::CloseView => self.close_view(),
+            Command::CycleView => self.cycle_view(),
+            Command::Cut => self.copy(register, true),
+            Command::Copy => self.copy(register, false),
+            Command::Paste => self.paste(register),
+            Command::CyclePaste => self.cycle_paste(last_paste),
+            Command::ToggleSoftWrap => self.toggle_soft_wrap(),
+            Command::ToggleMouse => self.toggle_mouse(),
+            Command::CycleLineNumbers => self.cycle_line_numbers(),
+            Command::ReloadConfig => self.reload_config(),
+            Command::OpenCommandLine => self.command_line(),
+            Command::ChooseRegister => {
+                match self.ask("Register (a-z, 0-9):") {
+                    Ok(KeyEvent { code: KeyCode::Char(name), .. }) if name.is_ascii_alphanumeric() => {
+                        self.register = Some(name);
+                        self.status_message = StatusMessage::from(format!("Register \"{}\" selected", name));
+                    },
+                    _ => self.status_message = StatusMessage::from(String::new()),
+                }
+            },
+            Command::Undo => {
+                self.anchor = None;
+                if let Some(position) = self.document_mut().undo() {
+                    self.cursor_position = position;
+                } else {
+                    self.status_message = StatusMessage::from("Nothing to undo.".to_string());
+                }
+            },
+            Command::Redo => {
+                self.anchor = None;
+                if let Some(position) = self.document_mut().redo() {
+                    self.cursor_position = position;
+                } else {
+                    self.status_message = StatusMessage::from("Nothing to redo.".to_string());
+                }
+            },
+        }
+    }
+
+    // Leaves without another word, whatever is unsaved.
+    fn quit(&mut self) {
+        for buffer in &mut self.buffers {
+            if buffer.document.owns_swap() {
+                buffer.document.remove_swap();
+            }
+        }
+        self.should_quit = true;
+    }
+
+    // Reads a command such as "w notes.txt" or "%s/foo/bar/g" and runs it. Up and Down walk
+    // through earlier commands, and Tab cycles through completions of what has been typed.
+    fn command_line(&mut self) {
+        let mut history_index = self.command_history.len();
+        let mut completions: Vec<String> = Vec::new();
+        let mut completion = 0;
+
+        let line = self
+            .prompt(":", |editor, key, line| {
+                match key.map(|key| key.code) {
+                    Some(code @ (KeyCode::Up | KeyCode::Down)) => {
+                        let index = if code == KeyCode::Up {
+                            history_index.saturating_sub(1)
+                        } else {
+                            (history_index + 1).min(editor.command_history.len())
+                        };
+                        // At either end of the history the line stays as typed.
+                        if index != history_index {
+                            history_index = index;
+                            *line = editor.command_history.get(history_index).cloned().unwrap_or_default();
+                        }
+                    },
+                    Some(code @ (KeyCode::Tab | KeyCode::BackTab)) => {
+                        if completions.is_empty() {
+                            completions = commandline::completions(line);
+                            completion = 0;
+                        } else if code == KeyCode::Tab {
+                            completion = (completion + 1) % completions.len();
+                        } else {
+                            completion = (completion + completions.len() - 1) % completions.len();
+                        }
+
+                        if let Some(completed) = completions.get(completion) {
+                            *line = completed.clone();
+                        }
+                        // With only one way to go, the next Tab carries on from there, say into a directory.
+                        if completions.len() > 1 {
+                            return;
+                        }
+                    },
+                    _ => (),
+                }
+                completions.clear();
+            })
+            .unwrap_or(None)
+            .filter(|line| !line.trim().is_empty());
+
+        let Some(line) = line else {
+            return;
+        };
+        if self.command_history.last() != Some(&line) {
+            self.command_history.push(line.clone());
+        }
+
+        match commandline::parse(&line) {
+            Ok(command) => self.run_ex(command),
+            Err(error) => self.status_message = StatusMessage::from(format!("ERR: {}", error)),
+        }
+    }
+
+    fn run_ex(&mut self, command: ExCommand) {
+        match command {
+            ExCommand::Write(None) => {
+                self.save();
+            },
+            // As in vi, only a buffer without a name of its own takes the one written to.
+            ExCommand::Write(Some(file_name)) if self.document().file_name.is_some() => {
+                self.status_message = match self.document().write_copy(&file_name) {
+                    Ok(()) => StatusMessage::from(format!("Wrote {}", file_name)),
+                    Err(error) => StatusMessage::from(format!("Error writing file: {}", error)),
+                };
+            },
+            ExCommand::Write(Some(file_name)) | ExCommand::SaveAs(file_name) => {
+                self.save_as(&file_name);
+            },
+            ExCommand::Quit { force } => {
+                let dirty_buffers = self.buffers.iter().filter(|buffer| buffer.document.is_dirty()).count();
+                if dirty_buffers > 0 && !force {
+                    self.status_message = StatusMessage::from(format!(
+                        "ERR: {} buffer(s) have unsaved changes (add ! to override)",
+                        dirty_buffers
+                    ));
+                    return;
+                }
+                self.quit();
+            },
+            ExCommand::WriteQuit => {
+                if self.save() {
+                    self.run_ex(ExCommand::Quit { force: false });
+                }
+            },
+            ExCommand::Edit(file_name) => self.open_path(file_name),
+            ExCommand::Goto(line) => {
+                let y = (line - 1).min(self.document().len().saturating_sub(1));
+                self.anchor = None;
+                self.document_mut().break_undo_group();
+                self.cursor_position = Position { x: vi::first_non_blank(self.document(), y), y };
+            },
+            ExCommand::Set(setting) => self.apply_setting(setting),
+            ExCommand::Substitute(substitution) => self.substitute(&substitution),
+            ExCommand::Run(command) => self.run_command(command, None, None),
+        }
+    }
+
+    // Tab width and soft tabs belong to the current buffer, the rest to the editor.
+    fn apply_setting(&mut self, setting: Setting) {
+        let message = match setting {
+            Setting::TabWidth(width) => {
+                let document = self.document_mut();
+                document.tab_width = width;
+                document.indent_size = width;
+                format!("Tab width {}", width)
+            },
+            Setting::SoftTabs(soft_tabs) => {
+                self.document_mut().soft_tabs = soft_tabs;
+                format!("Soft tabs {}", if soft_tabs { "on" } else { "off" })
+            },
+            Setting::SoftWrap(soft_wrap) => {
+                self.set_soft_wrap(soft_wrap);
+                return;
+            },
+            Setting::Mouse(mouse) => {
+                if mouse != self.mouse {
+                    self.toggle_mouse();
+                }
+                return;
+            },
+            Setting::LineNumbers(line_numbers) => {
+                self.line_numbers = line_numbers;
+                "Line numbers set".to_string()
+            },
+        };
+        self.status_message = StatusMessage::from(message);
+    }
+
+    // Replaces matches on the cursor's line, or all through the document, as one undo step.
+    fn substitute(&mut self, substitution: &Substitution) {
+        let Substitution { regex, replacement, global, whole_document } = substitution;
+        let mut last_line = if *whole_document { self.document().len() } else { self.cursor_position.y + 1 };
+        let mut at = Position { x: 0, y: if *whole_document { 0 } else { self.cursor_position.y } };
+        let mut replaced = 0;
+
+        self.anchor = None;
+        let cursor_position = self.cursor_position;
+        self.document_mut().begin_undo_group(&cursor_position);
+
+        while let Some(found) = self.document().find_regex(regex, &at, replacement) {
+            if found.start.y >= last_line {
+                break;
+            }
+
+            at = self.document_mut().replace(&found);
+            // A replacement with line breaks in it pushes the remaining lines down.
+            last_line += at.y - found.start.y;
+            if !global {
+                at = Position { x: 0, y: at.y + 1 };
+            } else if found.start == found.end {
+                at = self.document().next_position(&at);
+            }
+            self.cursor_position = found.start;
+            replaced += 1;
+        }
+
+        self.document_mut().end_undo_group();
+        self.clamp_cursor();
+        self.status_message = StatusMessage::from(if replaced == 0 {
+            format!("ERR: Pattern not found: {}", regex.as_str())
+        } else {
+            format!("Replaced {} occurrence(s).", replaced)
+        });
+    }
+
+    // Keys without a binding move the cursor or edit the text.
+    fn edit(&mut self, pressed_key: KeyEvent) {
+        match pressed_key.code {
+            KeyCode::Up | KeyCode::Down | KeyCode::Left | KeyCode::Right | KeyCode::PageUp | KeyCode:: PageDown | KeyCode::Home | KeyCode::End => {
+                self.move_cursor(pressed_key);
+            },
+            KeyCode::Delete => {
+              if self.selection().is_some() {
+                  self.delete_selection();
+              } else {
+                  let cursor_position = self.cursor_position;
+                  self.document_mut().delete(&cursor_position);
+              }
+            },
+            KeyCode::Backspace => {
+                if self.selection().is_some() {
+                    self.delete_selection();
+                } else {
+                    let cursor_position = self.cursor_position;
+                    self.cursor_position = self.document_mut().backspace(&cursor_position);
+                }
+            },
+            KeyCode::Tab => self.replace_selection(|editor| {
+                let cursor_position = editor.cursor_position;
+                let document = editor.document();
+                let text = if document.soft_tabs {
+                    let column = document.row(cursor_position.y).map_or(0, |row| row.columns(0, cursor_position.x, document.tab_width));
+                    " ".repeat(document.indent_size - column % document.indent_size)
+                } else {
+                    "\t".to_string()
+                };
+                editor.cursor_position = editor.document_mut().insert_str(&cursor_position, &text);
+            }),
+            KeyCode::Enter => self.replace_selection(|editor| {
+                let cursor_position = editor.cursor_position;
+                editor.document_mut().insert_newline(&cursor_position);
+                editor.cursor_position = Position {
+                    x: 0,
+                    y: editor.cursor_position.y.saturating_add(1),
+                };
+            }),
+            // Control chords nobody bound should not end up in the text.
+            KeyCode::Char(c) if !pressed_key.modifiers.intersects(KeyModifiers::CONTROL | KeyModifiers::ALT) => {
+                self.replace_selection(|editor| {
+                    let cursor_position = editor.cursor_position;
+                    editor.document_mut().insert(&cursor_position, c);
+                    editor.move_cursor_by_key(KeyCode::Right);
+                });
+            },
+            _ => (),
         }
+    }
 
-        self.shutdown();
+    fn mode(&self) -> Option<Mode> {
+        self.vi.as_ref().map(|vi| vi.mode)
     }
 
-    fn refresh_screen(&self) {
-        self.terminal.cursor_hide();
-        self.terminal.clear_screen();
-        self.terminal.cursor_position(&Position::default());
-        if self.should_quit {
-            println!("Good bye!");
-        } else {
-            self.draw_rows();
-            self.draw_status_bar();
-            self.draw_message_bar();
+    fn set_mode(&mut self, mode: Mode) {
+        if let Some(vi) = &mut self.vi {
+            vi.mode = mode;
+        }
+    }
 
-            self.terminal.cursor_position( &Position {
-                x: self.cursor_position.x.saturating_sub(self.offset.x),
-                y: self.cursor_position.y.saturating_sub(self.offset.y),
-            } );
+    // Takes `pressed_key` as a vi command if it is one. Insert mode types as usual until Esc,
+    // and chords with Ctrl or Alt still go through the keymap.
+    fn vi_keypress(&mut self, pressed_key: KeyEvent, register: Option<char>) -> bool {
+        let Some(vi) = &mut self.vi else {
+            return false;
+        };
+        if !self.pending_keys.is_empty() {
+            return false;
         }
 
-        self.terminal.cursor_show();
-        self.terminal.flush();
+        match (vi.mode, pressed_key.code) {
+            (Mode::Insert, KeyCode::Esc) => self.leave_insert(),
+            (Mode::Insert, _) => return false,
+            (_, KeyCode::Esc) => {
+                vi.cancel();
+                self.leave_visual();
+            },
+            (_, KeyCode::Char('r')) if pressed_key.modifiers == KeyModifiers::CONTROL => self.vi_run(None, Action::Redo, register),
+            _ => match vi::key_char(pressed_key) {
+                Some(c) => match vi.key(c) {
+                    Parse::Complete(count, action) => self.vi_run(count, action, register),
+                    // The register choice waits for the rest of the command.
+                    Parse::Incomplete => self.register = register,
+                    Parse::Invalid => (),
+                },
+                None if pressed_key.modifiers.intersects(KeyModifiers::CONTROL | KeyModifiers::ALT) => return false,
+                // Everything else goes to the keymap, except an unbound Tab, which would type.
+                None => {
+                    return pressed_key.code == KeyCode::Tab
+                        && matches!(self.keymap.lookup(&[keymap::key_of(pressed_key)]), Binding::Unbound);
+                },
+            },
+        }
+        true
     }
 
-    fn save(&mut self) {
-        if self.document.file_name.is_none() {
-
-            let new_name = self.prompt("Save as: ").unwrap_or(None);
+    fn vi_run(&mut self, count: Option<usize>, action: Action, register: Option<char>) {
+        let times = count.unwrap_or(1).max(1);
+        if self.mode() == Some(Mode::Normal) {
+            self.anchor = None;
+        }
+        if let Some(vi) = self.vi.as_mut().filter(|_| action.is_change()) {
+            vi.recording = Some(Change { count, action, typed: Vec::new() });
+        }
 
-            if new_name.is_none() {
-                self.status_message = StatusMessage::from("Save aborted!".to_string());
-                return;
-            }
-            self.document.file_name = new_name;
+        match action {
+            Action::Move(motion) => {
+                self.document_mut().break_undo_group();
+                if let Some(target) = vi::target(self.document(), self.cursor_position, motion, count) {
+                    self.cursor_position = target;
+                }
+            },
+            Action::Operate(operator, motion) => self.vi_operate(operator, motion, count, register),
+            Action::OperateSelection(operator) => {
+                if let Some((start, end)) = self.selection() {
+                    self.leave_visual();
+                    self.vi_apply(operator, start, end, false, register);
+                }
+            },
+            Action::Insert(entry) => self.vi_insert(entry),
+            Action::Paste { before } => self.vi_paste(before, times, register),
+            Action::Undo | Action::Redo => {
+                let command = if action == Action::Undo { Command::Undo } else { Command::Redo };
+                for _ in 0..times {
+                    self.run_command(command, None, None);
+                }
+            },
+            Action::Visual => {
+                self.anchor = Some(self.cursor_position);
+                self.set_mode(Mode::Visual);
+            },
+            Action::Escape => self.leave_visual(),
+            Action::Repeat => self.vi_repeat(count, register),
+            Action::CommandLine => self.command_line(),
         }
 
-        if self.document.save().is_ok() {
-            self.status_message = StatusMessage::from("File saved successfully".to_string());
-        } else {
-            self.status_message = StatusMessage::from("Error writing file!".to_string());
+        // A change that stays in Normal mode is already complete.
+        if self.mode() != Some(Mode::Insert) {
+            if let Some(vi) = &mut self.vi {
+                if let Some(change) = vi.recording.take() {
+                    vi.last_change = Some(change);
+                }
+            }
         }
     }
 
-    fn draw_status_bar(&self) {
-        // let spaces = " ".repeat(self.terminal.size().columns as usize);
-
-        let mut status;
-        let width = self.terminal.size().columns as usize;
-        let modified_indicator = if self.document.is_dirty() {
-            " (modified)"
-        } else {
-            ""
+    fn vi_operate(&mut self, operator: Operator, motion: Motion, count: Option<usize>, register: Option<char>) {
+        let at = self.cursor_position;
+        // cw on a word only changes up to its end, like ce.
+        let motion = match motion {
+            Motion::WordForward if operator == Operator::Change && !vi::is_blank(self.document(), at) => Motion::WordEnd,
+            motion => motion,
+        };
+        let Some(target) = vi::target(self.document(), at, motion, count) else {
+            return;
         };
 
-        let mut file_name = "[No Name]".to_string();
-        if let Some(name) = &self.document.file_name {
-            file_name = name.clone();
-            file_name.truncate(20);
-
+        if motion.linewise() {
+            let lines = (Position { x: 0, y: at.y.min(target.y) }, Position { x: 0, y: at.y.max(target.y) });
+            self.vi_apply(operator, lines.0, lines.1, true, register);
+            return;
         }
 
-
-        status = format!("{} - {} lines {}", file_name, self.document.len(), modified_indicator);
-
-
-
-        let line_indicator = format!("{}/{}",
-                                     self.cursor_position.y.saturating_add(1),
-                                     self.document.len(),
-                                    );
-
-        let len = status.len() + line_indicator.len();
-        if width > len {
-            status.push_str(&" ".repeat(width - len));
+        let (start, mut end) = if (target.y, target.x) < (at.y, at.x) { (target, at) } else { (at, target) };
+        let len = |y: usize| self.document().row(y).map_or(0, |row| row.len());
+        if motion.inclusive() {
+            end.x = (end.x + 1).min(len(end.y));
         }
-
-        status = format!("{}{}", status, line_indicator);
-
-        status.truncate(width);
-
-        self.terminal.set_bg_color(STATUS_BG_COLOR);
-        self.terminal.set_fg_color(STATUS_FG_COLOR);
-        println!("{}\r", status);
-        self.terminal.reset_bg_color();
-        self.terminal.reset_fg_color();
-    }
-
-    fn draw_message_bar(&self) {
-        self.terminal.clear_current_line();
-        let message = &self.status_message;
-        if Instant::now() - message.time < Duration::new(5, 0) {
-            let mut text = message.text.clone();
-            text.truncate(self.terminal.size().columns as usize);
-            print!("{}",text);
+        // dw on the last word of a line leaves the line break alone.
+        if motion == Motion::WordForward && end.y > start.y {
+            let line_end = Position { x: len(end.y - 1), y: end.y - 1 };
+            if (line_end.y, line_end.x) > (start.y, start.x) {
+                end = line_end;
+            }
         }
 
+        self.vi_apply(operator, start, end, false, register);
     }
 
-    pub fn shutdown(&self) {
-        self.terminal.shutdown();
-    }
-
-    fn draw_welcome_message(&self) {
-        let mut welcome_message = format!("Hecto Editor -- version({})\r", VERSION);
-        let width = self.terminal.size().columns as usize;
-        let len = welcome_message.len();
-        let padding = width.saturating_sub(len) / 2;
-        let spaces = " ".repeat(padding.saturating_sub(1));
+    // Carries out `operator` on the text from `start` up to `end`, or on the rows from `start.y`
+    // to `end.y` when `linewise`.
+    fn vi_apply(&mut self, operator: Operator, start: Position, end: Position, linewise: bool, register: Option<char>) {
+        self.document_mut().break_undo_group();
+        let (first, last) = (start.y, end.y);
 
-        welcome_message = format!("~{}{}", spaces, welcome_message);
-        welcome_message.truncate(width);
-        println!("{}\r", welcome_message);
+        if matches!(operator, Operator::Indent | Operator::Outdent) {
+            self.shift_lines(first, last, operator == Operator::Indent);
+            self.cursor_position = Position { x: vi::first_non_blank(self.document(), first), y: first };
+            return;
+        }
 
+        let (start, end) = if linewise {
+            (Position { x: 0, y: first }, Position { x: 0, y: last + 1 })
+        } else {
+            (start, end)
+        };
+        let mut text = self.document().text_range(&start, &end);
+        if linewise && !text.ends_with('\n') {
+            text.push('\n');
+        }
+        self.clipboard.yank(register, Yank { text, linewise });
+
+        match operator {
+            Operator::Yank if linewise => self.cursor_position.y = first,
+            Operator::Yank => self.cursor_position = start,
+            Operator::Delete => {
+                self.document_mut().delete_range(&start, &end);
+                self.cursor_position = start;
+                if linewise {
+                    let y = first.min(self.document().len().saturating_sub(1));
+                    self.cursor_position = Position { x: vi::first_non_blank(self.document(), y), y };
+                }
+            },
+            Operator::Change => {
+                // cc keeps the emptied line to type on.
+                let end = if linewise {
+                    Position { x: self.document().row(last).map_or(0, |row| row.len()), y: last }
+                } else {
+                    end
+                };
+                self.document_mut().delete_range(&start, &end);
+                self.cursor_position = start;
+                self.set_mode(Mode::Insert);
+            },
+            Operator::Indent | Operator::Outdent => (),
+        }
     }
 
-    pub fn draw_row(&self, row: &Row) {
+    // Moves rows `first` to `last` one indent level in or out, as a single undo step.
+    fn shift_lines(&mut self, first: usize, last: usize, indent: bool) {
+        let cursor_position = self.cursor_position;
+        let document = self.document_mut();
+        let unit = if document.soft_tabs { " ".repeat(document.indent_size) } else { "\t".to_string() };
 
-        let width = self.terminal.size().columns as usize;
-        let start = self.offset.x;
-        let end = self.offset.x + width;
-        let row = row.render(start, end);
+        document.begin_undo_group(&cursor_position);
+        for y in first..=last {
+            let Some(row) = document.row(y) else {
+                break;
+            };
 
-        println!("{}\r", row)
+            let line = row.as_str();
+            if indent && !line.is_empty() {
+                document.insert_str(&Position { x: 0, y }, &unit);
+            } else if !indent {
+                let width = if line.starts_with('\t') {
+                    1
+                } else {
+                    line.chars().take(document.indent_size).take_while(|c| *c == ' ').count()
+                };
+                document.delete_range(&Position { x: 0, y }, &Position { x: width, y });
+            }
+        }
+        document.end_undo_group();
     }
 
-    pub fn  draw_rows(&self) {
-        self.terminal.clear_screen();
-
-        let height = self.terminal.size().rows;
-        // let width = self.terminal.size().columns;
-        let is_empty = self.document.is_empty();
+    fn vi_insert(&mut self, entry: Entry) {
+        let Position { x, y } = self.cursor_position;
+        let len = self.document().row(y).map_or(0, |row| row.len());
+        self.document_mut().break_undo_group();
+
+        match entry {
+            Entry::Before => (),
+            Entry::After => self.cursor_position.x = (x + 1).min(len),
+            Entry::LineStart => self.cursor_position.x = vi::first_non_blank(self.document(), y),
+            Entry::LineEnd => self.cursor_position.x = len,
+            Entry::LineBelow => {
+                self.document_mut().insert_newline(&Position { x: len, y });
+                self.cursor_position = Position { x: 0, y: y + 1 };
+            },
+            Entry::LineAbove => {
+                self.document_mut().insert_newline(&Position { x: 0, y });
+                self.cursor_position = Position { x: 0, y };
+            },
+        }
+        self.set_mode(Mode::Insert);
+    }
 
-        for terminal_row in 0..height {
-            self.terminal.clear_current_line();
+    // p puts the text after the cursor, or whole lines below its line; P puts it before.
+    fn vi_paste(&mut self, before: bool, times: usize, register: Option<char>) {
+        let Some(yank) = self.clipboard.get(register, 0) else {
+            self.status_message = StatusMessage::from("Nothing to paste.".to_string());
+            return;
+        };
+        let yank = Yank { text: yank.text.repeat(times), linewise: yank.linewise };
+        let Position { x, y } = self.cursor_position;
 
-            if let Some(row) = self.document.row(terminal_row as usize + self.offset.y) {
-                self.draw_row(row);
-            } else if is_empty && terminal_row == height / 3 {
-                self.draw_welcome_message();
-            } else {
-                println!("~\r");
+        if yank.linewise {
+            if !before {
+                self.cursor_position = Position { x: 0, y: (y + 1).min(self.document().len()) };
             }
-
+            let (start, _) = self.insert_yank(&yank);
+            self.cursor_position = Position { x: vi::first_non_blank(self.document(), start.y), y: start.y };
+        } else {
+            let len = self.document().row(y).map_or(0, |row| row.len());
+            if !before {
+                self.cursor_position.x = (x + 1).min(len);
+            }
+            let (_, end) = self.insert_yank(&yank);
+            self.cursor_position = Position { x: end.x.saturating_sub(1), y: end.y };
         }
     }
 
-    pub fn process_keypress(&mut self) -> Result<(), std::io::Error> {
-        let event: Event = self.terminal.read_key().unwrap();
-
-        if let Event::Key(pressed_key) = event {
-
-            match (pressed_key.modifiers, pressed_key.code) {
+    // `.` makes the last change again, typing the same text if it went into Insert mode. A new
+    // count replaces the one it had.
+    fn vi_repeat(&mut self, count: Option<usize>, register: Option<char>) {
+        let Some(change) = self.vi.as_ref().and_then(|vi| vi.last_change.clone()) else {
+            return;
+        };
 
-                (KeyModifiers::CONTROL, KeyCode::Char('q')) => {
-                    if self.quit_times > 0 && self.document.is_dirty() {
-                        self.status_message = StatusMessage::from(format!(
-                           "WARNING! File has unsaved changes. Press ctrl+q {} more times to quit.",
-                            self.quit_times
-                        ));
-                        self.quit_times -= 1;
-                        return Ok(());
-                    }
+        self.vi_run(count.or(change.count), change.action, register);
+        if self.mode() == Some(Mode::Insert) {
+            for key in change.typed {
+                if let Some(vi) = &mut self.vi {
+                    vi.record(key);
+                }
+                self.edit(key);
+            }
+            self.leave_insert();
+        }
+    }
 
-                    self.should_quit = true;
-                },
-                (KeyModifiers::CONTROL, KeyCode::Char('s')) => self.save(),
-                (_, KeyCode::Up | KeyCode::Down | KeyCode::Left | KeyCode::Right | KeyCode::PageUp | KeyCode:: PageDown | KeyCode::Home | KeyCode::End) => {
-                    self.move_cursor(pressed_key);
-                },
-                (_, KeyCode::Delete) => {
-                  self.document.delete(&self.cursor_position);
-                },
-                (_, KeyCode::Backspace) => {
-                    if self.cursor_position.x > 0 || self.cursor_position.y > 0 {
-                        self.move_cursor_by_key(KeyCode::Left);
-                        self.document.delete(&self.cursor_position);
+    // Esc from Insert mode completes the change that opened it and steps back onto the last
+    // grapheme typed. A count on i, a, I or A types the text that many times.
+    fn leave_insert(&mut self) {
+        let change = self.vi.as_mut().and_then(|vi| vi.recording.take());
+        if let Some(change) = &change {
+            if matches!(change.action, Action::Insert(entry) if entry != Entry::LineBelow && entry != Entry::LineAbove) {
+                for _ in 1..change.count.unwrap_or(1) {
+                    for key in &change.typed {
+                        self.edit(*key);
                     }
-                },
-                (_, KeyCode::Enter) => {
-                    self.document.insert_newline(&self.cursor_position);
-                    self.move_cursor_by_key(KeyCode::Down);
-                },
-                (_, KeyCode::Char(c)) => {
-                    self.document.insert(&self.cursor_position, c);
-                    self.move_cursor_by_key(KeyCode::Right);
-
-                },
-                _ => {
-                    // println!("No idea {:?}", pressed_key);
-                },
-
+                }
+            }
+        }
 
+        if let Some(vi) = &mut self.vi {
+            vi.mode = Mode::Normal;
+            if change.is_some() {
+                vi.last_change = change;
             }
         }
+        self.document_mut().break_undo_group();
+        self.cursor_position.x = self.cursor_position.x.saturating_sub(1);
+    }
 
-        self.scroll();
-        if self.quit_times < QUIT_TIMES {
-            self.quit_times = QUIT_TIMES;
-            self.status_message = StatusMessage::from(String::new());
+    fn leave_visual(&mut self) {
+        if self.mode() == Some(Mode::Visual) {
+            self.anchor = None;
+            self.set_mode(Mode::Normal);
         }
-        Ok(())
     }
 
-    fn prompt(&mut self, prompt: &str) -> Result<Option<String>, std::io::Error> {
+    // Outside Insert mode the cursor sits on a grapheme rather than between two, so never past
+    // the end of a row or on the line after the last one.
+    fn vi_clamp(&mut self) {
+        match self.mode() {
+            None | Some(Mode::Insert) => return,
+            Some(Mode::Visual) if self.anchor.is_none() => self.set_mode(Mode::Normal),
+            _ => (),
+        }
+
+        let y = self.cursor_position.y.min(self.document().len().saturating_sub(1));
+        let len = self.document().row(y).map_or(0, |row| row.len());
+        self.cursor_position = Position { x: self.cursor_position.x.min(len.saturating_sub(1)), y };
+    }
+
+    // Reads a line of input. After every key, and with None after a paste, `callback` gets to
+    // look at the line so far and change it.
+    fn prompt<C>(&mut self, prompt: &str, mut callback: C) -> Result<Option<String>, std::io::Error>
+    where
+        C: FnMut(&mut Self, Option<KeyEvent>, &mut String),
+    {
         let mut result = String::new();
+        let mut cancelled = false;
 
         loop {
             self.status_message = StatusMessage::from(format!("{}{}", prompt, result));
             self.refresh_screen();
-            if let Event::Key(pressed_key) = self.terminal.read_key()? {
+            let event = self.terminal.read_key()?;
+
+            // A prompt holds a single line, so only the first line of a paste is kept.
+            if let Event::Paste(text) = &event {
+                result.push_str(text.split(['\r', '\n']).next().unwrap_or_default());
+                callback(self, None, &mut result);
+            }
+
+            if let Event::Key(pressed_key) = event {
 
                 match pressed_key.code {
                     KeyCode::Enter => break,
-                    KeyCode::Char(c) => {
-                        if pressed_key.modifiers != KeyModifiers::CONTROL {
-                            result.push(c);
-                        }
+                    KeyCode::Char(c) if pressed_key.modifiers != KeyModifiers::CONTROL => {
+                        result.push(c);
                     },
                     KeyCode::Backspace => {
-                        if !result.is_empty() {
-                            result.truncate(result.len()-1);
-                        }
+                        result.pop();
                     },
                     KeyCode::Esc => {
-                        result.truncate(0);
+                        cancelled = true;
                         break;
                     },
                     _ => (),
 
                 }
 
+                callback(self, Some(pressed_key), &mut result);
+
 
             }
 
         }
 
         self.status_message = StatusMessage::from(String::new());
-        if result.is_empty() {
+        if cancelled {
             return Ok(None);
         }
 
@@ -333,11 +1962,26 @@ impl Editor {
     }
 
     fn scroll(&mut self) {
+        let (width, height) = self.view_size();
+        if width == 0 || height == 0 {
+            return;
+        }
+
+        if self.soft_wrap {
+            self.scroll_wrapped();
+            return;
+        }
+
         let Position {x, y} = self.cursor_position;
-        let width = self.terminal.size().columns as usize;
-        let height = self.terminal.size().rows as usize;
 
-        let mut offset = &mut self.offset;
+        // Horizontal scrolling is by screen column, keeping all of a wide grapheme in view.
+        let tab_width = self.document().tab_width;
+        let (column, cursor_width) = self.document().row(y).map_or((0, 1), |row| {
+            let column = row.columns(0, x, tab_width);
+            (column, row.width_at(x, column, tab_width).max(1))
+        });
+
+        let offset = &mut self.offset;
         if y < offset.y {
             offset.y = y;
 
@@ -345,110 +1989,294 @@ impl Editor {
             offset.y = y.saturating_sub(height).saturating_add(1);
         }
 
-        if x < offset.x {
-            offset.x = x;
-        } else if x >= offset.x.saturating_add(width) {
-            offset.x = x.saturating_sub(width).saturating_add(1);
+        if column < offset.x {
+            offset.x = column;
+        } else if column + cursor_width > offset.x.saturating_add(width) {
+            offset.x = (column + cursor_width).saturating_sub(width);
         }
     }
 
-    pub fn move_cursor_by_key(&mut self, key: KeyCode) {
-        let terminal_height = self.terminal.size().rows as usize;
-        let Position { mut x, mut y } = self.cursor_position;
+    // With soft wrap, `offset.x` is the first screen line of row `offset.y` that is in view.
+    fn scroll_wrapped(&mut self) {
+        let (width, height) = self.view_size();
+        let Position { x, y } = self.cursor_position;
+        let segment = segment_of(&wrap_points(self.document(), y, width), x);
 
-        let height = self.document.len();
-        let mut width = if let Some(row) = self.document.row(y) {
-            row.len()
+        let top_lines = wrap_points(self.document(), self.offset.y, width).len();
+        self.offset.x = self.offset.x.min(top_lines - 1);
+        let top = (self.offset.y, self.offset.x);
+
+        if (y, segment) < top {
+            self.offset = Position { x: segment, y };
+            return;
+        }
+
+        // Walk up from the cursor's screen line; if the top is more than a view away, move it down.
+        let mut line = (y, segment);
+        for _ in 1..height {
+            if line <= top {
+                return;
+            }
+            line = self.previous_line(line, width);
+        }
+
+        if line > top {
+            self.offset = Position { x: line.1, y: line.0 };
+        }
+    }
+
+    // The screen line above (`row`, `segment`) when soft wrapping at `width`.
+    fn previous_line(&self, (row, segment): (usize, usize), width: usize) -> (usize, usize) {
+        if segment > 0 {
+            (row, segment - 1)
+        } else if row > 0 {
+            (row - 1, wrap_points(self.document(), row - 1, width).len() - 1)
         } else {
-            0
+            (0, 0)
+        }
+    }
+
+    // Where the cursor lands inside the active view's text area, or None when the wheel
+    // has scrolled it out of sight.
+    fn cursor_on_screen(&self) -> Option<Position> {
+        let Position { x, y } = self.cursor_position;
+        let (width, height) = self.view_size();
+        let document = self.document();
+        let position = if self.soft_wrap {
+            let points = wrap_points(document, y, width);
+            let segment = segment_of(&points, x);
+            if (y, segment) < (self.offset.y, self.offset.x) {
+                return None;
+            }
+
+            let lines: usize = (self.offset.y..y).map(|row| wrap_points(document, row, width).len()).sum();
+            Position {
+                x: document.row(y).map_or(0, |row| row.columns(points[segment], x, document.tab_width)),
+                y: lines + segment - self.offset.x,
+            }
+        } else {
+            let column = document.row(y).map_or(0, |row| row.columns(0, x, document.tab_width));
+            Position {
+                x: column.checked_sub(self.offset.x)?,
+                y: y.checked_sub(self.offset.y)?,
+            }
         };
 
-        match key {
-            KeyCode::Up => y = y.saturating_sub(1),
-            KeyCode::Down => {
-                if y < height {
-                    y = y.saturating_add(1);
+        (position.x < width && position.y < height).then_some(position)
+    }
+
+    fn handle_mouse(&mut self, mouse: MouseEvent) {
+        let (column, row) = (mouse.column as usize, mouse.row as usize);
+        let rects = self.view_rects();
+        let under = rects.iter().find(|(_, rect)| {
+            (rect.x..rect.x + rect.width).contains(&column) && (rect.y..rect.y + rect.height).contains(&row)
+        });
+
+        match mouse.kind {
+            MouseEventKind::Down(MouseButton::Left) => {
+                let Some((view, rect)) = under else {
+                    return;
+                };
+                if *view != self.active_view {
+                    self.focus_view(*view);
                 }
-            },
-            KeyCode::Left => {
-                if x > 0 {
-                    x -= 1;
-                } else if y > 0 {
-                    y -= 1;
-                    if let Some(row) = self.document.row(y) {
-                        x = row.len();
-                    } else {
-                        x = 0;
-                    }
+                // Clicks on the status bar only focus the view.
+                if row + 1 >= rect.y + rect.height {
+                    return;
                 }
 
-            },
-            KeyCode::Right => {
-                if x < width {
-                    x += 1;
-                } else if y < height {
-                    y += 1;
-                    x = 0;
+                self.document_mut().break_undo_group();
+                let position = self.position_at(*view, rect, column, row);
+                let double_click = self.last_click.take().is_some_and(|(time, last)| {
+                    last == position && time.elapsed() < DOUBLE_CLICK_TIME
+                });
+
+                if double_click {
+                    let word = self.document().row(position.y).map_or(0..0, |row| row.word_at(position.x));
+                    self.anchor = Some(Position { x: word.start, y: position.y });
+                    self.cursor_position = Position { x: word.end, y: position.y };
+                } else {
+                    self.anchor = None;
+                    self.cursor_position = position;
+                    self.last_click = Some((Instant::now(), position));
                 }
+                self.scroll();
             },
-            KeyCode::PageUp => {
-                y = if y > terminal_height {
-                    y - terminal_height
-                } else {
-                    0
+            // Dragging selects from where the button went down, within the focused view.
+            MouseEventKind::Drag(MouseButton::Left) => {
+                let Some((view, rect)) = rects.iter().find(|(view, _)| *view == self.active_view) else {
+                    return;
                 };
+                let row = row.clamp(rect.y, (rect.y + rect.height).saturating_sub(2));
+                let column = column.clamp(rect.x, (rect.x + rect.width).saturating_sub(1));
+
+                if self.anchor.is_none() {
+                    self.anchor = Some(self.cursor_position);
+                }
+                self.cursor_position = self.position_at(*view, rect, column, row);
+                self.scroll();
             },
-            KeyCode::PageDown => {
-                y = if y.saturating_add(terminal_height) < height {
-                    y + terminal_height as usize
-                } else {
-                    height
-                };
+            MouseEventKind::ScrollUp | MouseEventKind::ScrollDown => {
+                if let Some((view, rect)) = under {
+                    self.scroll_view(*view, rect, mouse.kind == MouseEventKind::ScrollDown);
+                }
             },
-            KeyCode::Home => x = 0,
-            KeyCode::End => x = width,
             _ => (),
         }
+    }
 
-        width = if let Some(row) = self.document.row(y) {
-            row.len()
+    // The document position shown at screen cell (`column`, `row`) of `view`, which fills `rect`.
+    fn position_at(&self, view: usize, rect: &Rect, column: usize, row: usize) -> Position {
+        let state = self.view_state(view);
+        let document = &self.buffers[state.buffer].document;
+        let gutter = self.gutter_width(document);
+        let width = rect.width.saturating_sub(gutter);
+        let column = column.saturating_sub(rect.x + gutter);
+        let mut line = row.saturating_sub(rect.y);
+
+        if !self.soft_wrap {
+            let y = (state.offset.y + line).min(document.len());
+            let x = document.row(y).map_or(0, |row| row.index_at_column(0, state.offset.x + column, document.tab_width));
+            return Position { x, y };
+        }
+
+        let (mut y, mut segment) = (state.offset.y, state.offset.x);
+        loop {
+            let points = wrap_points(document, y, width);
+            // Another view may have shortened the row this one is scrolled into.
+            segment = segment.min(points.len().saturating_sub(1));
+            if segment + line < points.len() || y >= document.len() {
+                let segment = (segment + line).min(points.len() - 1);
+                let Some(row) = document.row(y) else {
+                    return Position { x: 0, y };
+                };
+                // Past the end of a line that continues below, stop on its last grapheme.
+                let last = points.get(segment + 1).map_or(row.len(), |next| next.saturating_sub(1));
+                return Position { x: row.index_at_column(points[segment], column, document.tab_width).min(last), y };
+            }
+
+            line = line.saturating_sub(points.len() - segment);
+            segment = 0;
+            y += 1;
+        }
+    }
+
+    // Moves `view` a few screen lines up or down, leaving its cursor where it is.
+    fn scroll_view(&mut self, view: usize, rect: &Rect, down: bool) {
+        let mut state = self.view_state(view);
+        let document = &self.buffers[state.buffer].document;
+        let width = rect.width.saturating_sub(self.gutter_width(document));
+        let last = document.len().saturating_sub(1);
+
+        for _ in 0..WHEEL_LINES {
+            let Position { x: segment, y } = state.offset;
+            state.offset = if !self.soft_wrap {
+                let y = if down { (y + 1).min(last) } else { y.saturating_sub(1) };
+                Position { y, ..state.offset }
+            } else if down {
+                if segment + 1 < wrap_points(document, y, width).len() {
+                    Position { x: segment + 1, y }
+                } else if y < last {
+                    Position { x: 0, y: y + 1 }
+                } else {
+                    state.offset
+                }
+            } else if segment > 0 {
+                Position { x: segment - 1, y }
+            } else if y > 0 {
+                Position { x: wrap_points(document, y - 1, width).len() - 1, y: y - 1 }
+            } else {
+                state.offset
+            };
+        }
+
+        if view == self.active_view {
+            self.offset = state.offset;
         } else {
-            0
-        };
+            self.views[view] = state;
+        }
+    }
 
-        if x > width {
-            x = width;
+    // `offset.x` means something else with and without wrapping, so every view starts over at the left.
+    fn set_soft_wrap(&mut self, soft_wrap: bool) {
+        self.soft_wrap = soft_wrap;
+        for view in &mut self.views {
+            view.offset.x = 0;
         }
+        self.offset.x = 0;
+        self.scroll();
+    }
 
-        self.cursor_position = Position { x, y }
+    fn toggle_mouse(&mut self) {
+        self.mouse = !self.mouse;
+        self.terminal.capture_mouse(self.mouse);
+
+        let state = if self.mouse { "on" } else { "off, terminal selection works again" };
+        self.status_message = StatusMessage::from(format!("Mouse {}", state));
+    }
+
+    // Up and Down by screen line rather than by row, staying in the same screen column.
+    fn move_wrapped(&self, x: usize, y: usize, down: bool) -> (usize, usize) {
+        let (width, _) = self.view_size();
+        let document = self.document();
+        let points = wrap_points(document, y, width);
+        let segment = segment_of(&points, x);
+        let column = document.row(y).map_or(0, |row| row.columns(points[segment], x, document.tab_width));
+
+        let (y, segment) = if down {
+            if segment + 1 < points.len() {
+                (y, segment + 1)
+            } else if y < document.len() {
+                (y + 1, 0)
+            } else {
+                return (x, y);
+            }
+        } else if segment > 0 {
+            (y, segment - 1)
+        } else if y > 0 {
+            (y - 1, wrap_points(document, y - 1, width).len() - 1)
+        } else {
+            return (x, y);
+        };
+
+        let points = wrap_points(document, y, width);
+        let Some(row) = document.row(y) else {
+            return (0, y);
+        };
+        // Past the end of a line that continues below, stop on its last grapheme.
+        let last = points.get(segment + 1).map_or(row.len(), |next| next.saturating_sub(1));
+        (row.index_at_column(points[segment], column, document.tab_width).min(last), y)
     }
 
+    fn toggle_soft_wrap(&mut self) {
+        self.set_soft_wrap(!self.soft_wrap);
 
-    pub fn move_cursor(&mut self, pressed_key: KeyEvent) {
-        let terminal_height = self.terminal.size().rows as usize;
+        let state = if self.soft_wrap { "on" } else { "off" };
+        self.status_message = StatusMessage::from(format!("Soft wrap {}", state));
+    }
+
+    pub fn move_cursor_by_key(&mut self, key: KeyCode) {
+        let (_, terminal_height) = self.view_size();
         let Position { mut x, mut y } = self.cursor_position;
 
-        let height = self.document.len();
-        let mut width = if let Some(row) = self.document.row(y) {
+        let height = self.document().len();
+        let mut width = if let Some(row) = self.document().row(y) {
             row.len()
         } else {
             0
         };
 
-
-        match pressed_key.code {
+        match key {
+            KeyCode::Up if self.soft_wrap => (x, y) = self.move_wrapped(x, y, false),
+            KeyCode::Down if self.soft_wrap => (x, y) = self.move_wrapped(x, y, true),
             KeyCode::Up => y = y.saturating_sub(1),
-            KeyCode::Down => {
-                if y < height {
-                    y = y.saturating_add(1);
-                }
-            },
+            KeyCode::Down if y < height => y = y.saturating_add(1),
             KeyCode::Left => {
                 if x > 0 {
                     x -= 1;
                 } else if y > 0 {
                     y -= 1;
-                    if let Some(row) = self.document.row(y) {
+                    if let Some(row) = self.document().row(y) {
                         x = row.len();
                     } else {
                         x = 0;
@@ -464,16 +2292,10 @@ impl Editor {
                     x = 0;
                 }
             },
-            KeyCode::PageUp => {
-                y = if y > terminal_height {
-                    y - terminal_height
-                } else {
-                    0
-                };
-            },
+            KeyCode::PageUp => y = y.saturating_sub(terminal_height),
             KeyCode::PageDown => {
                 y = if y.saturating_add(terminal_height) < height {
-                    y + terminal_height as usize
+                    y + terminal_height
                 } else {
                     height
                 };
@@ -483,7 +2305,7 @@ impl Editor {
             _ => (),
         }
 
-        width = if let Some(row) = self.document.row(y) {
+        width = if let Some(row) = self.document().row(y) {
             row.len()
         } else {
             0
@@ -494,8 +2316,58 @@ impl Editor {
         }
 
         self.cursor_position = Position { x, y }
+    }
+
+
+    // Shift extends the selection from where it started; plain movement drops it, and
+    // Left/Right first collapse it onto the matching end.
+    pub fn move_cursor(&mut self, pressed_key: KeyEvent) {
+        self.document_mut().break_undo_group();
+
+        if pressed_key.modifiers.contains(KeyModifiers::SHIFT) {
+            if self.anchor.is_none() {
+                self.anchor = Some(self.cursor_position);
+            }
+            self.move_cursor_by_key(pressed_key.code);
+            return;
+        }
 
+        let selection = self.selection();
+        self.anchor = None;
+        match (selection, pressed_key.code) {
+            (Some((start, _)), KeyCode::Left) => self.cursor_position = start,
+            (Some((_, end)), KeyCode::Right) => self.cursor_position = end,
+            _ => self.move_cursor_by_key(pressed_key.code),
+        }
     }
 
 
-}
\ No newline at end of file
+}
+
+// Where each screen line of row `y` starts when soft wrapping at `width`. The line past
+// the end of the document is a single empty one.
+// Reads `file_name` into a document set up for editing. A file that doesn't exist yet opens
+// empty, to be created by the first save.
+fn open_document(file_name: &str, config: &Config) -> Result<Document, std::io::Error> {
+    let mut document = match Document::open(file_name) {
+        Ok(document) => document,
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => {
+            let mut document = Document::default();
+            document.file_name = Some(file_name.to_string());
+            document.load_editorconfig();
+            document
+        },
+        Err(error) => return Err(error),
+    };
+    config.configure(&mut document);
+    Ok(document)
+}
+
+fn wrap_points(document: &Document, y: usize, width: usize) -> Vec<usize> {
+    document.row(y).map_or_else(|| vec![0], |row| row.wrap_points(width, document.tab_width))
+}
+
+// Which of a row's screen lines the grapheme at `x` is on.
+fn segment_of(points: &[usize], x: usize) -> usize {
+    points.iter().rposition(|point| *point <= x).unwrap_or(0)
+}
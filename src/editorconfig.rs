@@ -0,0 +1,319 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::document::LineEnding;
+
+use regex::Regex;
+
+// Numeric brace ranges wider than this match any number rather than being spelled out.
+const MAX_RANGE: i64 = 1000;
+
+// What the .editorconfig files above a file say about it. None leaves the editor's own setting.
+#[derive(Default, Clone, Copy)]
+pub struct Properties {
+    pub soft_tabs: Option<bool>,
+    pub indent_size: Option<usize>,
+    pub tab_width: Option<usize>,
+    pub end_of_line: Option<LineEnding>,
+    pub bom: Option<bool>,
+    pub trim_trailing_whitespace: Option<bool>,
+    pub insert_final_newline: Option<bool>,
+}
+
+struct Section {
+    // None for a glob that could not be made sense of, which then matches nothing.
+    glob: Option<Regex>,
+    pairs: Vec<(String, String)>,
+}
+
+impl Properties {
+    // Walks up from the file to the first .editorconfig marked root. Nearer files, and later
+    // sections within a file, override what came before.
+    pub fn for_file(path: &Path) -> Self {
+        let path = absolute(path);
+        let mut files = Vec::new();
+        let mut directory = path.parent();
+
+        while let Some(current) = directory {
+            if let Ok(contents) = fs::read_to_string(current.join(".editorconfig")) {
+                let (root, sections) = parse(&contents);
+                files.push((current, sections));
+                if root {
+                    break;
+                }
+            }
+            directory = current.parent();
+        }
+
+        let mut values: HashMap<String, String> = HashMap::new();
+        for (directory, sections) in files.iter().rev() {
+            let Ok(relative) = path.strip_prefix(directory) else {
+                continue;
+            };
+            let relative = relative.to_string_lossy().replace('\\', "/");
+
+            for section in sections {
+                if !section.glob.as_ref().is_some_and(|glob| glob.is_match(&relative)) {
+                    continue;
+                }
+                for (key, value) in &section.pairs {
+                    if value == "unset" {
+                        values.remove(key);
+                    } else {
+                        values.insert(key.clone(), value.clone());
+                    }
+                }
+            }
+        }
+
+        Self::from_values(&values)
+    }
+
+    fn from_values(values: &HashMap<String, String>) -> Self {
+        let value = |key: &str| values.get(key).map(String::as_str);
+        let number = |key: &str| value(key).and_then(|number| number.parse::<usize>().ok()).filter(|number| *number > 0);
+        let flag = |key: &str| match value(key) {
+            Some("true") => Some(true),
+            Some("false") => Some(false),
+            _ => None,
+        };
+
+        // Each of tab_width and indent_size stands in for the other when only one is given.
+        let tab_width = number("tab_width").or_else(|| number("indent_size"));
+        let indent_size = if value("indent_size") == Some("tab") {
+            tab_width
+        } else {
+            number("indent_size")
+        };
+
+        Self {
+            soft_tabs: match value("indent_style") {
+                Some("space") => Some(true),
+                Some("tab") => Some(false),
+                _ => None,
+            },
+            indent_size,
+            tab_width,
+            end_of_line: match value("end_of_line") {
+                Some("lf") => Some(LineEnding::Lf),
+                Some("crlf") => Some(LineEnding::Crlf),
+                _ => None,
+            },
+            // Only the UTF-8 charsets can be honored; the rest are left alone.
+            bom: match value("charset") {
+                Some("utf-8") => Some(false),
+                Some("utf-8-bom") => Some(true),
+                _ => None,
+            },
+            trim_trailing_whitespace: flag("trim_trailing_whitespace"),
+            insert_final_newline: flag("insert_final_newline"),
+        }
+    }
+}
+
+fn absolute(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| {
+        env::current_dir().map_or_else(|_| path.to_path_buf(), |directory| directory.join(path))
+    })
+}
+
+// Whether the preamble sets root = true, and the sections in file order.
+fn parse(contents: &str) -> (bool, Vec<Section>) {
+    let mut root = false;
+    let mut sections: Vec<Section> = Vec::new();
+
+    for line in contents.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+
+        if let Some(glob) = line.strip_prefix('[').and_then(|line| line.strip_suffix(']')) {
+            sections.push(Section { glob: glob_regex(glob), pairs: Vec::new() });
+            continue;
+        }
+
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+        let key = key.trim().to_lowercase();
+        let value = value.trim().to_lowercase();
+
+        match sections.last_mut() {
+            Some(section) => section.pairs.push((key, value)),
+            None if key == "root" => root = value == "true",
+            None => (),
+        }
+    }
+
+    (root, sections)
+}
+
+// Translates an EditorConfig glob into a regex over paths relative to the .editorconfig.
+fn glob_regex(glob: &str) -> Option<Regex> {
+    // A glob without a slash matches the file name in any directory below.
+    let (prefix, glob) = match glob.strip_prefix('/') {
+        Some(glob) => ("", glob),
+        None if glob.contains('/') => ("", glob),
+        None => ("(?:.*/)?", glob),
+    };
+
+    let chars: Vec<char> = glob.chars().collect();
+    let mut pattern = format!("^{}", prefix);
+    let mut alternations = 0;
+    let mut index = 0;
+
+    while index < chars.len() {
+        match chars[index] {
+            '\\' if index + 1 < chars.len() => {
+                index += 1;
+                pattern.push_str(&regex::escape(&chars[index].to_string()));
+            },
+            '*' if chars.get(index + 1) == Some(&'*') => {
+                index += 1;
+                pattern.push_str(".*");
+            },
+            '*' => pattern.push_str("[^/]*"),
+            '?' => pattern.push_str("[^/]"),
+            '[' => match chars[index + 1..].iter().position(|c| *c == ']') {
+                Some(length) => {
+                    let class: String = chars[index + 1..index + 1 + length].iter().collect();
+                    let (negated, class) = match class.strip_prefix('!') {
+                        Some(class) => (true, class.to_string()),
+                        None => (false, class),
+                    };
+                    let class = class
+                        .replace('\\', "\\\\")
+                        .replace('[', "\\[")
+                        .replace('&', "\\&")
+                        .replace('~', "\\~")
+                        .replace('^', "\\^");
+                    pattern.push_str(&format!("[{}{}]", if negated { "^" } else { "" }, class));
+                    index += length + 1;
+                },
+                None => pattern.push_str("\\["),
+            },
+            '{' => {
+                let close = closing_brace(&chars, index);
+                let inner: String = close.map_or_else(String::new, |close| chars[index + 1..close].iter().collect());
+
+                if let (Some(close), Some(range)) = (close, number_range(&inner)) {
+                    pattern.push_str(&range);
+                    index = close;
+                } else if close.is_some() && inner.contains(',') {
+                    alternations += 1;
+                    pattern.push_str("(?:");
+                } else {
+                    pattern.push_str("\\{");
+                }
+            },
+            ',' if alternations > 0 => pattern.push('|'),
+            '}' if alternations > 0 => {
+                alternations -= 1;
+                pattern.push(')');
+            },
+            c => pattern.push_str(&regex::escape(&c.to_string())),
+        }
+        index += 1;
+    }
+
+    pattern.push('$');
+    Regex::new(&pattern).ok()
+}
+
+fn closing_brace(chars: &[char], open: usize) -> Option<usize> {
+    let mut depth = 0;
+    for (index, c) in chars.iter().enumerate().skip(open) {
+        match c {
+            '{' => depth += 1,
+            '}' if depth == 1 => return Some(index),
+            '}' => depth -= 1,
+            _ => (),
+        }
+    }
+    None
+}
+
+// "{3..12}" matches any whole number from 3 to 12.
+fn number_range(inner: &str) -> Option<String> {
+    let (from, to) = inner.split_once("..")?;
+    let (from, to) = (from.parse::<i64>().ok()?, to.parse::<i64>().ok()?);
+    let (from, to) = (from.min(to), from.max(to));
+
+    if to - from > MAX_RANGE {
+        return Some("[+-]?[0-9]+".to_string());
+    }
+
+    let numbers: Vec<String> = (from..=to).map(|number| number.to_string()).collect();
+    Some(format!("(?:{})", numbers.join("|")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(glob: &str, path: &str) -> bool {
+        glob_regex(glob).is_some_and(|regex| regex.is_match(path))
+    }
+
+    #[test]
+    fn star_stays_within_a_directory() {
+        assert!(matches("*.rs", "main.rs"));
+        assert!(matches("*.rs", "src/main.rs"));
+        assert!(matches("src/*.rs", "src/main.rs"));
+        assert!(!matches("src/*.rs", "src/bin/main.rs"));
+        assert!(!matches("*.rs", "main.rsx"));
+    }
+
+    #[test]
+    fn double_star_crosses_directories() {
+        assert!(matches("src/**.rs", "src/main.rs"));
+        assert!(matches("src/**.rs", "src/bin/main.rs"));
+        assert!(matches("/**/Makefile", "a/b/Makefile"));
+        assert!(!matches("src/**.rs", "tests/main.rs"));
+    }
+
+    #[test]
+    fn leading_slash_anchors_to_the_directory() {
+        assert!(matches("/Makefile", "Makefile"));
+        assert!(!matches("/Makefile", "sub/Makefile"));
+    }
+
+    #[test]
+    fn question_mark_and_classes() {
+        assert!(matches("file?.txt", "file1.txt"));
+        assert!(!matches("file?.txt", "file10.txt"));
+        assert!(matches("[ab].c", "a.c"));
+        assert!(!matches("[ab].c", "c.c"));
+        assert!(matches("[!ab].c", "c.c"));
+        assert!(!matches("[!ab].c", "a.c"));
+    }
+
+    #[test]
+    fn braces_expand_alternatives() {
+        assert!(matches("*.{js,ts}", "index.js"));
+        assert!(matches("*.{js,ts}", "index.ts"));
+        assert!(!matches("*.{js,ts}", "index.rs"));
+        assert!(matches("{a,{b,c}}.md", "c.md"));
+        assert!(matches("{README}.md", "{README}.md"));
+        assert!(!matches("{README}.md", "README.md"));
+    }
+
+    #[test]
+    fn braces_expand_number_ranges() {
+        assert!(matches("log{3..12}", "log3"));
+        assert!(matches("log{3..12}", "log12"));
+        assert!(!matches("log{3..12}", "log13"));
+        assert!(matches("log{12..3}", "log7"));
+        assert!(matches("log{0..5000}", "log4321"));
+    }
+
+    #[test]
+    fn escapes_are_literal() {
+        assert!(matches("\\*.txt", "*.txt"));
+        assert!(!matches("\\*.txt", "a.txt"));
+        assert!(matches("a+b.txt", "a+b.txt"));
+    }
+}
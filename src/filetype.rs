@@ -0,0 +1,158 @@
+use std::path::Path;
+
+pub struct FileType {
+    name: String,
+    hl_opts: HighlightingOptions,
+}
+
+#[derive(Default)]
+pub struct HighlightingOptions {
+    numbers: bool,
+    characters: bool,
+    headings: bool,
+    string_delimiters: Vec<char>,
+    comment: Option<&'static str>,
+    multiline_comment: Option<(&'static str, &'static str)>,
+    primary_keywords: Vec<String>,
+    secondary_keywords: Vec<String>,
+}
+
+impl Default for FileType {
+    fn default() -> Self {
+        Self {
+            name: String::from("No filetype"),
+            hl_opts: HighlightingOptions::default(),
+        }
+    }
+}
+
+impl FileType {
+    pub fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    pub fn highlighting_options(&self) -> &HighlightingOptions {
+        &self.hl_opts
+    }
+
+    pub fn from(file_name: &str) -> Self {
+        let extension = Path::new(file_name)
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .unwrap_or_default();
+
+        match extension {
+            "rs" => Self {
+                name: String::from("Rust"),
+                hl_opts: HighlightingOptions {
+                    numbers: true,
+                    characters: true,
+                    string_delimiters: vec!['"'],
+                    comment: Some("//"),
+                    multiline_comment: Some(("/*", "*/")),
+                    primary_keywords: keywords(&[
+                        "as", "async", "await", "break", "const", "continue", "crate", "dyn",
+                        "else", "enum", "extern", "false", "fn", "for", "if", "impl", "in",
+                        "let", "loop", "match", "mod", "move", "mut", "pub", "ref", "return",
+                        "self", "Self", "static", "struct", "super", "trait", "true", "type",
+                        "unsafe", "use", "where", "while",
+                    ]),
+                    secondary_keywords: keywords(&[
+                        "bool", "char", "i8", "i16", "i32", "i64", "i128", "isize", "u8", "u16",
+                        "u32", "u64", "u128", "usize", "f32", "f64", "str", "String", "Vec",
+                        "Box", "Option", "Result", "Some", "None", "Ok", "Err",
+                    ]),
+                    ..HighlightingOptions::default()
+                },
+            },
+            "py" => Self {
+                name: String::from("Python"),
+                hl_opts: HighlightingOptions {
+                    numbers: true,
+                    string_delimiters: vec!['"', '\''],
+                    comment: Some("#"),
+                    multiline_comment: Some(("\"\"\"", "\"\"\"")),
+                    primary_keywords: keywords(&[
+                        "False", "None", "True", "and", "as", "assert", "async", "await",
+                        "break", "class", "continue", "def", "del", "elif", "else", "except",
+                        "finally", "for", "from", "global", "if", "import", "in", "is",
+                        "lambda", "nonlocal", "not", "or", "pass", "raise", "return", "try",
+                        "while", "with", "yield",
+                    ]),
+                    secondary_keywords: keywords(&[
+                        "int", "float", "str", "bool", "bytes", "list", "dict", "set", "tuple",
+                        "object", "self", "print", "len", "range",
+                    ]),
+                    ..HighlightingOptions::default()
+                },
+            },
+            "md" | "markdown" => Self {
+                name: String::from("Markdown"),
+                hl_opts: HighlightingOptions {
+                    headings: true,
+                    string_delimiters: vec!['`'],
+                    multiline_comment: Some(("<!--", "-->")),
+                    ..HighlightingOptions::default()
+                },
+            },
+            "toml" => Self {
+                name: String::from("TOML"),
+                hl_opts: HighlightingOptions {
+                    numbers: true,
+                    string_delimiters: vec!['"', '\''],
+                    comment: Some("#"),
+                    primary_keywords: keywords(&["true", "false"]),
+                    ..HighlightingOptions::default()
+                },
+            },
+            "json" => Self {
+                name: String::from("JSON"),
+                hl_opts: HighlightingOptions {
+                    numbers: true,
+                    string_delimiters: vec!['"'],
+                    primary_keywords: keywords(&["true", "false", "null"]),
+                    ..HighlightingOptions::default()
+                },
+            },
+            _ => Self::default(),
+        }
+    }
+}
+
+impl HighlightingOptions {
+    pub fn numbers(&self) -> bool {
+        self.numbers
+    }
+
+    pub fn characters(&self) -> bool {
+        self.characters
+    }
+
+    pub fn headings(&self) -> bool {
+        self.headings
+    }
+
+    pub fn string_delimiters(&self) -> &[char] {
+        &self.string_delimiters
+    }
+
+    pub fn comment(&self) -> Option<&str> {
+        self.comment
+    }
+
+    pub fn multiline_comment(&self) -> Option<(&str, &str)> {
+        self.multiline_comment
+    }
+
+    pub fn primary_keywords(&self) -> &[String] {
+        &self.primary_keywords
+    }
+
+    pub fn secondary_keywords(&self) -> &[String] {
+        &self.secondary_keywords
+    }
+}
+
+fn keywords(words: &[&str]) -> Vec<String> {
+    words.iter().map(|word| word.to_string()).collect()
+}
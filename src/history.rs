@@ -0,0 +1,285 @@
+use crate::document::LineEnding;
+use crate::position::Position;
+
+use unicode_segmentation::UnicodeSegmentation;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum EditKind {
+    Insert,
+    Delete,
+}
+
+pub struct Edit {
+    pub kind: EditKind,
+    pub at: Position,
+    pub text: String,
+}
+
+impl Edit {
+    // Position just past the text, as it sits in the document after an insert.
+    pub fn end(&self) -> Position {
+        end_of(&self.at, &self.text)
+    }
+}
+
+pub fn end_of(at: &Position, text: &str) -> Position {
+    let mut lines = text.split('\n');
+    let first = lines.next().unwrap_or_default();
+    let mut end = Position {
+        x: at.x + first.graphemes(true).count(),
+        y: at.y,
+    };
+    for line in lines {
+        end.x = line.graphemes(true).count();
+        end.y += 1;
+    }
+    end
+}
+
+pub struct Step {
+    pub edits: Vec<Edit>,
+    pub cursor_before: Position,
+    pub cursor_after: Position,
+    // The document's line ending before and after, for steps that switched it.
+    pub line_ending: Option<(LineEnding, LineEnding)>,
+}
+
+pub struct History {
+    undo: Vec<Step>,
+    redo: Vec<Step>,
+    saved_at: Option<usize>,
+    coalesce: bool,
+    group: Option<Step>,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self {
+            undo: Vec::new(),
+            redo: Vec::new(),
+            saved_at: Some(0),
+            coalesce: false,
+            group: None,
+        }
+    }
+}
+
+impl History {
+    pub fn record(&mut self, edit: Edit, cursor_before: Position, cursor_after: Position) {
+        self.invalidate_redo();
+        if let Some(group) = &mut self.group {
+            group.edits.push(edit);
+            group.cursor_after = cursor_after;
+            return;
+        }
+
+        self.undo.push(Step {
+            edits: vec![edit],
+            cursor_before,
+            cursor_after,
+            line_ending: None,
+        });
+        self.coalesce = false;
+    }
+
+    // A typed character joins the previous step if it continues the same run of typing.
+    pub fn record_typed(&mut self, edit: Edit, cursor_after: Position) {
+        if self.coalesce && self.saved_at != Some(self.undo.len()) {
+            if let Some(step) = self.undo.last_mut() {
+                if let Some(last) = step.edits.last_mut() {
+                    if last.kind == EditKind::Insert && last.end() == edit.at {
+                        last.text.push_str(&edit.text);
+                        step.cursor_after = cursor_after;
+                        return;
+                    }
+                }
+            }
+        }
+
+        let cursor_before = edit.at;
+        self.record(edit, cursor_before, cursor_after);
+        self.coalesce = true;
+    }
+
+    // Collects every edit recorded until `end_group` into one undo step.
+    pub fn begin_group(&mut self, cursor: Position) {
+        self.coalesce = false;
+        self.group = Some(Step {
+            edits: Vec::new(),
+            cursor_before: cursor,
+            cursor_after: cursor,
+            line_ending: None,
+        });
+    }
+
+    // Undoing the open group switches the line ending back from `after` to `before`.
+    pub fn record_line_ending(&mut self, before: LineEnding, after: LineEnding) {
+        if before == after {
+            return;
+        }
+        if let Some(group) = &mut self.group {
+            group.line_ending = Some((before, after));
+        }
+    }
+
+    pub fn end_group(&mut self) {
+        if let Some(group) = self.group.take() {
+            if !group.edits.is_empty() || group.line_ending.is_some() {
+                self.invalidate_redo();
+                self.undo.push(group);
+            }
+        }
+    }
+
+    // Stops the current run of typing from absorbing the next character.
+    pub fn seal(&mut self) {
+        self.coalesce = false;
+    }
+
+    pub fn undo(&mut self) -> Option<Step> {
+        self.coalesce = false;
+        self.undo.pop()
+    }
+
+    pub fn redo(&mut self) -> Option<Step> {
+        self.coalesce = false;
+        self.redo.pop()
+    }
+
+    pub fn push_undone(&mut self, step: Step) {
+        self.redo.push(step);
+    }
+
+    pub fn push_redone(&mut self, step: Step) {
+        self.undo.push(step);
+    }
+
+    pub fn mark_saved(&mut self) {
+        self.coalesce = false;
+        self.saved_at = Some(self.undo.len());
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        self.saved_at != Some(self.undo.len())
+    }
+
+    fn invalidate_redo(&mut self) {
+        if let Some(saved_at) = self.saved_at {
+            if saved_at > self.undo.len() {
+                self.saved_at = None;
+            }
+        }
+        self.redo.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn insert(x: usize, text: &str) -> Edit {
+        Edit { kind: EditKind::Insert, at: Position { x, y: 0 }, text: text.to_string() }
+    }
+
+    fn at(x: usize) -> Position {
+        Position { x, y: 0 }
+    }
+
+    #[test]
+    fn typing_coalesces_into_one_step() {
+        let mut history = History::default();
+        history.record_typed(insert(0, "a"), at(1));
+        history.record_typed(insert(1, "b"), at(2));
+        history.record_typed(insert(2, "c"), at(3));
+
+        let step = history.undo().unwrap();
+        assert_eq!(step.edits.len(), 1);
+        assert_eq!(step.edits[0].text, "abc");
+        assert!(step.cursor_after == at(3));
+        assert!(history.undo().is_none());
+    }
+
+    #[test]
+    fn a_seal_or_a_jump_starts_a_new_step() {
+        let mut history = History::default();
+        history.record_typed(insert(0, "a"), at(1));
+        history.seal();
+        history.record_typed(insert(1, "b"), at(2));
+        history.record_typed(insert(5, "c"), at(6));
+
+        assert_eq!(history.undo().unwrap().edits[0].text, "c");
+        assert_eq!(history.undo().unwrap().edits[0].text, "b");
+        assert_eq!(history.undo().unwrap().edits[0].text, "a");
+    }
+
+    #[test]
+    fn groups_undo_as_one_step() {
+        let mut history = History::default();
+        history.begin_group(at(0));
+        history.record(insert(0, "a"), at(0), at(1));
+        history.record(insert(1, "b"), at(1), at(2));
+        history.end_group();
+
+        let step = history.undo().unwrap();
+        assert_eq!(step.edits.len(), 2);
+        assert!(step.cursor_before == at(0));
+        assert!(step.cursor_after == at(2));
+    }
+
+    #[test]
+    fn empty_groups_leave_no_step() {
+        let mut history = History::default();
+        history.begin_group(at(0));
+        history.end_group();
+        assert!(history.undo().is_none());
+        assert!(!history.is_dirty());
+    }
+
+    #[test]
+    fn undoing_back_to_the_save_is_clean() {
+        let mut history = History::default();
+        history.record(insert(0, "a"), at(0), at(1));
+        history.mark_saved();
+        assert!(!history.is_dirty());
+
+        history.record(insert(1, "b"), at(1), at(2));
+        assert!(history.is_dirty());
+
+        let step = history.undo().unwrap();
+        history.push_undone(step);
+        assert!(!history.is_dirty());
+
+        let step = history.redo().unwrap();
+        history.push_redone(step);
+        assert!(history.is_dirty());
+    }
+
+    #[test]
+    fn typing_after_a_save_does_not_join_the_saved_step() {
+        let mut history = History::default();
+        history.record_typed(insert(0, "a"), at(1));
+        history.mark_saved();
+        history.record_typed(insert(1, "b"), at(2));
+
+        let step = history.undo().unwrap();
+        history.push_undone(step);
+        assert!(!history.is_dirty());
+    }
+
+    #[test]
+    fn a_new_edit_after_undoing_past_the_save_is_never_clean() {
+        let mut history = History::default();
+        history.record(insert(0, "a"), at(0), at(1));
+        history.mark_saved();
+
+        let step = history.undo().unwrap();
+        history.push_undone(step);
+        history.record(insert(0, "b"), at(0), at(1));
+        assert!(history.is_dirty());
+        assert!(history.redo().is_none());
+
+        let step = history.undo().unwrap();
+        history.push_undone(step);
+        assert!(history.is_dirty());
+    }
+}
@@ -0,0 +1,316 @@
+use std::collections::HashMap;
+use std::fs;
+
+use crate::config::config_dir;
+
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+pub type Key = (KeyModifiers, KeyCode);
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Command {
+    Quit,
+    Save,
+    Find,
+    Replace,
+    ReplaceAll,
+    ToggleLineEnding,
+    Open,
+    CloseBuffer,
+    ChooseBuffer,
+    NextBuffer,
+    PreviousBuffer,
+    SplitHorizontal,
+    SplitVertical,
+    CloseView,
+    CycleView,
+    Cut,
+    Copy,
+    Paste,
+    CyclePaste,
+    ChooseRegister,
+    ToggleSoftWrap,
+    ToggleMouse,
+    CycleLineNumbers,
+    ReloadConfig,
+    OpenCommandLine,
+    Undo,
+    Redo,
+}
+
+// Names used for commands in keys.toml.
+const COMMANDS: &[(&str, Command)] = &[
+    ("quit", Command::Quit),
+    ("save", Command::Save),
+    ("find", Command::Find),
+    ("replace", Command::Replace),
+    ("replace-all", Command::ReplaceAll),
+    ("toggle-line-ending", Command::ToggleLineEnding),
+    ("open", Command::Open),
+    ("close-buffer", Command::CloseBuffer),
+    ("choose-buffer", Command::ChooseBuffer),
+    ("next-buffer", Command::NextBuffer),
+    ("previous-buffer", Command::PreviousBuffer),
+    ("split-horizontal", Command::SplitHorizontal),
+    ("split-vertical", Command::SplitVertical),
+    ("close-view", Command::CloseView),
+    ("cycle-view", Command::CycleView),
+    ("cut", Command::Cut),
+    ("copy", Command::Copy),
+    ("paste", Command::Paste),
+    ("cycle-paste", Command::CyclePaste),
+    ("choose-register", Command::ChooseRegister),
+    ("toggle-soft-wrap", Command::ToggleSoftWrap),
+    ("toggle-mouse", Command::ToggleMouse),
+    ("cycle-line-numbers", Command::CycleLineNumbers),
+    ("reload-config", Command::ReloadConfig),
+    ("command-line", Command::OpenCommandLine),
+    ("undo", Command::Undo),
+    ("redo", Command::Redo),
+];
+
+const DEFAULT_BINDINGS: &[(&str, Command)] = &[
+    ("Ctrl+Q", Command::Quit),
+    ("Ctrl+S", Command::Save),
+    ("Ctrl+F", Command::Find),
+    ("Ctrl+R", Command::Replace),
+    ("Alt+R", Command::ReplaceAll),
+    ("Alt+L", Command::ToggleLineEnding),
+    ("Ctrl+O", Command::Open),
+    ("Ctrl+W", Command::CloseBuffer),
+    ("Ctrl+B", Command::ChooseBuffer),
+    ("Alt+N", Command::NextBuffer),
+    ("Alt+P", Command::PreviousBuffer),
+    ("Alt+S", Command::SplitHorizontal),
+    ("Alt+V", Command::SplitVertical),
+    ("Alt+X", Command::CloseView),
+    ("Alt+O", Command::CycleView),
+    ("Ctrl+X", Command::Cut),
+    ("Ctrl+C", Command::Copy),
+    ("Ctrl+V", Command::Paste),
+    ("Alt+Y", Command::CyclePaste),
+    ("Alt+\"", Command::ChooseRegister),
+    ("Alt+W", Command::ToggleSoftWrap),
+    ("Alt+M", Command::ToggleMouse),
+    ("Alt+#", Command::CycleLineNumbers),
+    ("Alt+C", Command::ReloadConfig),
+    ("Ctrl+P", Command::OpenCommandLine),
+    ("Ctrl+Z", Command::Undo),
+    ("Ctrl+Y", Command::Redo),
+];
+
+pub enum Binding {
+    Command(Command),
+    // The keys so far start a longer chord.
+    Prefix,
+    Unbound,
+}
+
+pub struct Keymap {
+    bindings: HashMap<Vec<Key>, Command>,
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        let bindings = DEFAULT_BINDINGS
+            .iter()
+            .filter_map(|(keys, command)| Some((parse_keys(keys)?, *command)))
+            .collect();
+
+        Self { bindings }
+    }
+}
+
+impl Keymap {
+    // The defaults with the user's keys.toml on top. Entries that make no sense are left out
+    // and described in the returned errors.
+    pub fn load() -> (Self, Vec<String>) {
+        let mut keymap = Self::default();
+        let Some(path) = config_dir().map(|dir| dir.join("keys.toml")) else {
+            return (keymap, Vec::new());
+        };
+        let Ok(contents) = fs::read_to_string(&path) else {
+            return (keymap, Vec::new());
+        };
+
+        let table = match contents.parse::<toml::Table>() {
+            Ok(table) => table,
+            Err(error) => return (keymap, vec![format!("{}: {}", path.display(), error.message())]),
+        };
+
+        let mut errors = Vec::new();
+        for (keys, value) in table {
+            let Some(parsed) = parse_keys(&keys) else {
+                errors.push(format!("keys.toml: \"{}\" is not a key", keys));
+                continue;
+            };
+
+            match value.as_str() {
+                // "none" takes a default binding away.
+                Some("none") => {
+                    keymap.bindings.remove(&parsed);
+                },
+                Some(name) => match command_named(name) {
+                    Some(command) => {
+                        keymap.bindings.insert(parsed, command);
+                    },
+                    None => errors.push(format!("keys.toml: \"{}\" for {} is not a command", name, keys)),
+                },
+                None => errors.push(format!("keys.toml: the command for {} must be a string", keys)),
+            }
+        }
+
+        (keymap, errors)
+    }
+
+    pub fn lookup(&self, keys: &[Key]) -> Binding {
+        if let Some(command) = self.bindings.get(keys) {
+            Binding::Command(*command)
+        } else if self.bindings.keys().any(|chord| chord.len() > keys.len() && chord.starts_with(keys)) {
+            Binding::Prefix
+        } else {
+            Binding::Unbound
+        }
+    }
+}
+
+pub fn command_named(name: &str) -> Option<Command> {
+    COMMANDS.iter().find(|(command_name, _)| *command_name == name).map(|(_, command)| *command)
+}
+
+pub fn command_names() -> impl Iterator<Item = &'static str> {
+    COMMANDS.iter().map(|(name, _)| *name)
+}
+
+// Shift is already part of a typed character, so it is dropped from those to match bindings.
+pub fn key_of(event: KeyEvent) -> Key {
+    match event.code {
+        KeyCode::Char(_) => (event.modifiers - KeyModifiers::SHIFT, event.code),
+        code => (event.modifiers, code),
+    }
+}
+
+pub fn key_name((modifiers, code): Key) -> String {
+    let mut name = String::new();
+    for (modifier, prefix) in [(KeyModifiers::CONTROL, "Ctrl+"), (KeyModifiers::ALT, "Alt+"), (KeyModifiers::SHIFT, "Shift+")] {
+        if modifiers.contains(modifier) {
+            name.push_str(prefix);
+        }
+    }
+
+    match code {
+        KeyCode::Char(' ') => name.push_str("Space"),
+        KeyCode::Char(c) if c.is_uppercase() => name.push_str(&format!("Shift+{}", c)),
+        KeyCode::Char(c) => name.extend(c.to_uppercase()),
+        code => name.push_str(&code.to_string()),
+    }
+    name
+}
+
+// A space separated chord such as "Ctrl+K Ctrl+C".
+fn parse_keys(text: &str) -> Option<Vec<Key>> {
+    let keys = text.split_whitespace().map(parse_key).collect::<Option<Vec<Key>>>()?;
+    (!keys.is_empty()).then_some(keys)
+}
+
+fn parse_key(text: &str) -> Option<Key> {
+    // The key itself may be a '+', as in "Ctrl++".
+    let (modifiers, key) = match text.strip_suffix('+') {
+        Some(rest) if rest.is_empty() || rest.ends_with('+') => (rest.strip_suffix('+').unwrap_or(rest), "+"),
+        _ => text.rsplit_once('+').unwrap_or(("", text)),
+    };
+
+    let mut parsed = KeyModifiers::NONE;
+    for modifier in modifiers.split('+').filter(|modifier| !modifier.is_empty()) {
+        parsed |= match modifier.to_lowercase().as_str() {
+            "ctrl" | "control" => KeyModifiers::CONTROL,
+            "alt" | "meta" => KeyModifiers::ALT,
+            "shift" => KeyModifiers::SHIFT,
+            _ => return None,
+        };
+    }
+
+    let code = match key.to_lowercase().as_str() {
+        "enter" | "return" => KeyCode::Enter,
+        "esc" | "escape" => KeyCode::Esc,
+        "tab" => KeyCode::Tab,
+        "backspace" => KeyCode::Backspace,
+        "delete" | "del" => KeyCode::Delete,
+        "insert" | "ins" => KeyCode::Insert,
+        "up" => KeyCode::Up,
+        "down" => KeyCode::Down,
+        "left" => KeyCode::Left,
+        "right" => KeyCode::Right,
+        "home" => KeyCode::Home,
+        "end" => KeyCode::End,
+        "pageup" => KeyCode::PageUp,
+        "pagedown" => KeyCode::PageDown,
+        "space" => KeyCode::Char(' '),
+        name => match name.strip_prefix('f').and_then(|number| number.parse::<u8>().ok()) {
+            Some(number) if (1..=12).contains(&number) => KeyCode::F(number),
+            _ => {
+                let mut chars = key.chars();
+                let (Some(c), None) = (chars.next(), chars.next()) else {
+                    return None;
+                };
+                KeyCode::Char(c.to_lowercase().next().unwrap_or(c))
+            },
+        },
+    };
+
+    Some(match code {
+        KeyCode::Char(c) if parsed.contains(KeyModifiers::SHIFT) => {
+            (parsed - KeyModifiers::SHIFT, KeyCode::Char(c.to_uppercase().next().unwrap_or(c)))
+        },
+        code => (parsed, code),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_key_reads_modifiers_and_names() {
+        assert_eq!(parse_key("Ctrl+S"), Some((KeyModifiers::CONTROL, KeyCode::Char('s'))));
+        assert_eq!(parse_key("ctrl+alt+pagedown"), Some((KeyModifiers::CONTROL | KeyModifiers::ALT, KeyCode::PageDown)));
+        assert_eq!(parse_key("Alt+Space"), Some((KeyModifiers::ALT, KeyCode::Char(' '))));
+        assert_eq!(parse_key("F5"), Some((KeyModifiers::NONE, KeyCode::F(5))));
+        assert_eq!(parse_key("Meta+Enter"), Some((KeyModifiers::ALT, KeyCode::Enter)));
+    }
+
+    #[test]
+    fn parse_key_takes_plus_as_a_key() {
+        assert_eq!(parse_key("+"), Some((KeyModifiers::NONE, KeyCode::Char('+'))));
+        assert_eq!(parse_key("Ctrl++"), Some((KeyModifiers::CONTROL, KeyCode::Char('+'))));
+    }
+
+    #[test]
+    fn shift_on_a_letter_matches_the_typed_character() {
+        assert_eq!(parse_key("Shift+A"), Some((KeyModifiers::NONE, KeyCode::Char('A'))));
+        assert_eq!(parse_key("Ctrl+Shift+a"), Some((KeyModifiers::CONTROL, KeyCode::Char('A'))));
+        assert_eq!(parse_key("Shift+Tab"), Some((KeyModifiers::SHIFT, KeyCode::Tab)));
+    }
+
+    #[test]
+    fn parse_key_rejects_nonsense() {
+        assert_eq!(parse_key("Hyper+A"), None);
+        assert_eq!(parse_key("Ctrl+ab"), None);
+        assert_eq!(parse_key("F13"), None);
+        assert_eq!(parse_key(""), None);
+        assert_eq!(parse_keys("  "), None);
+    }
+
+    #[test]
+    fn chords_bind_and_prefix() {
+        assert!(DEFAULT_BINDINGS.iter().all(|(keys, _)| parse_keys(keys).is_some()));
+
+        let keymap = Keymap {
+            bindings: HashMap::from([(parse_keys("Ctrl+K Ctrl+C").unwrap(), Command::Copy)]),
+        };
+        let ctrl = |c| (KeyModifiers::CONTROL, KeyCode::Char(c));
+        assert!(matches!(keymap.lookup(&[ctrl('k')]), Binding::Prefix));
+        assert!(matches!(keymap.lookup(&[ctrl('k'), ctrl('c')]), Binding::Command(Command::Copy)));
+        assert!(matches!(keymap.lookup(&[ctrl('c')]), Binding::Unbound));
+    }
+}
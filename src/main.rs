@@ -4,11 +4,25 @@ mod editor;
 mod terminal;
 mod position;
 mod document;
+mod buffer;
 mod row;
 mod statusmessage;
+mod history;
+mod highlighting;
+mod filetype;
+mod swap;
+mod diff;
+mod diskstate;
+mod view;
+mod clipboard;
+mod screen;
+mod keymap;
+mod config;
+mod editorconfig;
+mod vi;
+mod commandline;
 
 use crate::editor::Editor;
-use crate::position::Position;
 
 
 fn main() {
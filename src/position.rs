@@ -1,4 +1,4 @@
-#[derive(Default)]
+#[derive(Default, Clone, Copy, PartialEq)]
 pub struct Position {
     pub x: usize,
     pub y: usize,
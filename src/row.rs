@@ -1,11 +1,21 @@
 use std::cmp;
+use std::ops::Range;
 
+use crate::editor::SearchDirection;
+use crate::filetype::HighlightingOptions;
+use crate::highlighting;
+use crate::screen::{Cell, Style};
+
+use regex::Regex;
+use ropey::RopeSlice;
 use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
 
 
 #[derive(Default)]
 pub struct Row {
     string: String,
+    highlighting: Vec<highlighting::Type>,
     len: usize,
 }
 
@@ -14,6 +24,7 @@ impl From<&str> for Row {
     fn from(slice: &str) -> Self {
         let mut row = Self {
             string: String::from(slice),
+            highlighting: Vec::new(),
             len:0,
         };
         row.update_len();
@@ -22,83 +33,431 @@ impl From<&str> for Row {
 
 }
 
+impl From<RopeSlice<'_>> for Row {
+
+    // A line from the document's rope, minus its line terminator.
+    fn from(line: RopeSlice<'_>) -> Self {
+        let mut string = line.to_string();
+        if string.ends_with('\n') {
+            string.pop();
+            if string.ends_with('\r') {
+                string.pop();
+            }
+        }
+
+        Self::from(&string[..])
+    }
+
+}
+
 impl Row {
-    pub fn render(&self, start: usize, end: usize) -> String {
-        let end = cmp::min(end, self.string.len());
-        let start = cmp::min(start, end);
-        let mut result = String::new();
+    // Lays out the screen line that starts at grapheme `start` as cells, skipping its first
+    // `first_column` columns and clipping it to `width`, with the `selected` graphemes reversed.
+    // A selection reaching past the row's end marks the line break too.
+    pub fn render(
+        &self,
+        start: usize,
+        first_column: usize,
+        width: usize,
+        tab_width: usize,
+        selected: Option<Range<usize>>,
+    ) -> Vec<Cell> {
+        let end_column = first_column + width;
+        let mut cells: Vec<Cell> = Vec::with_capacity(width);
+        let mut column = 0;
+        let mut at_end = true;
 
-        for grapheme in self.string[..]
-            .graphemes(true)
-            .skip(start)
-            .take(end-start)
-        {
-            if grapheme == "\t" {
-                result.push_str("    ");
-            } else {
-                result.push_str(grapheme);
+        for (index, grapheme) in self.string[..].graphemes(true).enumerate().skip(start) {
+            let grapheme_width = grapheme_width(grapheme, column, tab_width);
+            let next_column = column + grapheme_width;
+            if next_column <= first_column && column < first_column {
+                column = next_column;
+                continue;
+            }
+            if column >= end_column {
+                at_end = false;
+                break;
+            }
+
+            let style = Style {
+                fg: self.highlighting
+                    .get(index)
+                    .copied()
+                    .unwrap_or(highlighting::Type::None)
+                    .to_color(),
+                reverse: selected.as_ref().is_some_and(|selected| selected.contains(&index)),
+                ..Style::default()
+            };
+
+            // Zero-width graphemes ride along with whatever is drawn before them.
+            if grapheme_width == 0 {
+                if let Some(cell) = cells.iter_mut().rev().find(|cell| !cell.symbol.is_empty()) {
+                    cell.symbol.push_str(grapheme);
+                }
+                continue;
             }
 
+            // Tabs, and wide graphemes cut by either edge, become blanks for the columns in view.
+            if grapheme == "\t" || column < first_column || next_column > end_column {
+                let visible = cmp::min(next_column, end_column) - cmp::max(column, first_column);
+                cells.extend((0..visible).map(|_| Cell::from(" ", style)));
+            } else {
+                cells.push(Cell::from(grapheme, style));
+                cells.extend((1..grapheme_width).map(|_| Cell::continuation(style)));
+            }
+            column = next_column;
         }
 
-        result
+        let line_break_selected = selected.is_some_and(|selected| selected.contains(&self.len));
+        if line_break_selected && at_end && column >= first_column && column < end_column {
+            cells.push(Cell::from(" ", Style { reverse: true, ..Style::default() }));
+        }
 
+        cells
     }
 
     pub fn len(&self) -> usize {
         self.len
     }
 
-    pub fn is_empty(&self) -> bool {
-        self.len == 0
+    // Grapheme indices at which each screen line starts when the row is wrapped at `width`
+    // columns. A row that exactly fills its last line gets an empty one for the cursor to sit on.
+    pub fn wrap_points(&self, width: usize, tab_width: usize) -> Vec<usize> {
+        let mut points = vec![0];
+        let mut columns = 0;
+
+        for (index, grapheme) in self.string[..].graphemes(true).enumerate() {
+            let mut grapheme_width = grapheme_width(grapheme, columns, tab_width);
+            if columns + grapheme_width > width && columns > 0 {
+                points.push(index);
+                columns = 0;
+                grapheme_width = self::grapheme_width(grapheme, columns, tab_width);
+            }
+            columns += grapheme_width;
+        }
+
+        if width > 0 && columns >= width {
+            points.push(self.len);
+        }
+        points
+    }
+
+    // Screen columns taken by the graphemes from `start` up to `end`, laid out from column 0.
+    pub fn columns(&self, start: usize, end: usize, tab_width: usize) -> usize {
+        self.string[..]
+            .graphemes(true)
+            .skip(start)
+            .take(end.saturating_sub(start))
+            .fold(0, |columns, grapheme| columns + grapheme_width(grapheme, columns, tab_width))
+    }
+
+    // The grapheme covering screen column `column` of the line starting at `start`, or the row's end.
+    pub fn index_at_column(&self, start: usize, column: usize, tab_width: usize) -> usize {
+        let mut columns = 0;
+        for (index, grapheme) in self.string[..].graphemes(true).enumerate().skip(start) {
+            columns += grapheme_width(grapheme, columns, tab_width);
+            if columns > column {
+                return index;
+            }
+        }
+        self.len
+    }
+
+    // Screen columns the grapheme at `index` takes when it starts at `column`.
+    pub fn width_at(&self, index: usize, column: usize, tab_width: usize) -> usize {
+        self.string[..]
+            .graphemes(true)
+            .nth(index)
+            .map_or(0, |grapheme| grapheme_width(grapheme, column, tab_width))
+    }
+
+    // The word around grapheme `at`, or just that grapheme when it is not part of one.
+    pub fn word_at(&self, at: usize) -> Range<usize> {
+        let graphemes: Vec<&str> = self.string[..].graphemes(true).collect();
+        let is_word = |grapheme: &&str| grapheme.chars().all(|c| c.is_alphanumeric() || c == '_');
+
+        if !graphemes.get(at).is_some_and(is_word) {
+            return at..cmp::min(at + 1, self.len);
+        }
+
+        let start = graphemes[..at].iter().rposition(|grapheme| !is_word(grapheme)).map_or(0, |index| index + 1);
+        let end = graphemes[at..].iter().position(|grapheme| !is_word(grapheme)).map_or(self.len, |index| at + index);
+        start..end
     }
 
     fn update_len(&mut self) {
         self.len = self.string[..].graphemes(true).count();
     }
 
-    pub fn insert(&mut self, at: usize, c: char) {
-        if at > self.len() {
-            self.string.push(c);
+    // Number of chars covered by the first `at` graphemes.
+    pub fn char_index(&self, at: usize) -> usize {
+        self.string[..]
+            .graphemes(true)
+            .take(at)
+            .map(|grapheme| grapheme.chars().count())
+            .sum()
+    }
+
+    pub fn find(&self, query: &str, at: usize, direction: SearchDirection) -> Option<usize> {
+        if at > self.len || query.is_empty() {
+            return None;
+        }
+
+        let start = if direction == SearchDirection::Forward { at } else { 0 };
+        let end = if direction == SearchDirection::Forward { self.len } else { at };
+
+        let substring: String = self.string[..]
+            .graphemes(true)
+            .skip(start)
+            .take(end - start)
+            .collect();
+
+        let matching_byte_index = if direction == SearchDirection::Forward {
+            substring.find(query)
         } else {
-            let mut result: String = self.string[..].graphemes(true).take(at).collect();
-            let remainder: String = self.string[..].graphemes(true).skip(at).collect();
+            substring.rfind(query)
+        };
 
-            result.push(c);
-            result.push_str(&remainder);
-            self.string = result;
+        if let Some(matching_byte_index) = matching_byte_index {
+            for (grapheme_index, (byte_index, _)) in substring[..].grapheme_indices(true).enumerate() {
+                if matching_byte_index == byte_index {
+                    return Some(start + grapheme_index);
+                }
+            }
         }
 
-        self.update_len();
+        None
     }
 
-    pub fn delete(&mut self, at: usize) {
-        if at >= self.len() {
-            return;
-        } else {
-            let mut result: String = self.string[..].graphemes(true).take(at).collect();
-            let remainder: String = self.string[..].graphemes(true).skip(at + 1).collect();
-            result.push_str(&remainder);
-            self.string = result;
+    // Grapheme range of the first match at or after `at`, with capture references in
+    // `replacement` expanded against it.
+    pub fn find_regex(&self, regex: &Regex, at: usize, replacement: &str) -> Option<(usize, usize, String)> {
+        if at > self.len {
+            return None;
+        }
+
+        let offset = self.string[..]
+            .grapheme_indices(true)
+            .nth(at)
+            .map_or(self.string.len(), |(byte_index, _)| byte_index);
+
+        let captures = regex.captures_at(&self.string, offset)?;
+        let found = captures.get(0)?;
+        let mut expanded = String::new();
+        captures.expand(replacement, &mut expanded);
+
+        Some((
+            self.grapheme_index(found.start()),
+            self.grapheme_index(found.end()),
+            expanded,
+        ))
+    }
+
+    // Colors the row and returns whether it ends inside a multi-line comment.
+    pub fn highlight(&mut self, opts: &HighlightingOptions, word: Option<&Regex>, start_with_comment: bool) -> bool {
+        let (byte_types, in_comment) = self.highlight_syntax(opts, start_with_comment);
+
+        let mut highlighting: Vec<highlighting::Type> = self.string[..]
+            .grapheme_indices(true)
+            .map(|(byte_index, _)| byte_types[byte_index])
+            .collect();
+
+        if let Some(word) = word {
+            for found in word.find_iter(&self.string).filter(|found| !found.is_empty()) {
+                let start = self.grapheme_index(found.start());
+                let end = self.grapheme_index(found.end());
+                for highlight in highlighting.iter_mut().take(end).skip(start) {
+                    *highlight = highlighting::Type::Match;
+                }
+            }
         }
-        self.update_len();
+
+        self.highlighting = highlighting;
+        in_comment
     }
 
-    pub fn append(&mut self, new: &Self) {
-        self.string = format!("{}{}", self.string, new.string);
-        self.update_len();
+    // Works on byte offsets so delimiters can be matched with plain string prefixes.
+    fn highlight_syntax(&self, opts: &HighlightingOptions, start_with_comment: bool) -> (Vec<highlighting::Type>, bool) {
+        let text = &self.string[..];
+        let mut types = vec![highlighting::Type::None; text.len()];
+        let mut in_comment = start_with_comment && opts.multiline_comment().is_some();
+        let mut prev_is_separator = true;
+        let mut index = 0;
+
+        if opts.headings() && text.starts_with('#') {
+            types.fill(highlighting::Type::PrimaryKeywords);
+            return (types, in_comment);
+        }
+
+        while index < text.len() {
+            let rest = &text[index..];
+
+            if let Some((start, end)) = opts.multiline_comment() {
+                if in_comment || rest.starts_with(start) {
+                    let search_from = if in_comment { 0 } else { start.len() };
+                    let closing = rest[search_from..].find(end);
+                    let comment_len = closing.map_or(rest.len(), |closing| search_from + closing + end.len());
+
+                    types[index..index + comment_len].fill(highlighting::Type::MultilineComment);
+                    in_comment = closing.is_none();
+                    index += comment_len;
+                    prev_is_separator = true;
+                    continue;
+                }
+            }
+
+            if let Some(comment) = opts.comment() {
+                if rest.starts_with(comment) {
+                    types[index..].fill(highlighting::Type::Comment);
+                    break;
+                }
+            }
+
+            let c = rest.chars().next().unwrap_or_default();
+
+            if opts.string_delimiters().contains(&c) {
+                let len = delimited_len(rest, c).unwrap_or(rest.len());
+                types[index..index + len].fill(highlighting::Type::String);
+                index += len;
+                prev_is_separator = true;
+                continue;
+            }
+
+            if opts.characters() && c == '\'' {
+                let literal = delimited_len(rest, c)
+                    .filter(|len| rest[..*len].chars().count() <= 4 || rest.starts_with("'\\u"));
+                if let Some(len) = literal {
+                    types[index..index + len].fill(highlighting::Type::Character);
+                    index += len;
+                    prev_is_separator = true;
+                    continue;
+                }
+            }
+
+            if opts.numbers() && prev_is_separator && c.is_ascii_digit() {
+                let len = rest
+                    .find(|c: char| !(c.is_ascii_alphanumeric() || c == '.' || c == '_'))
+                    .unwrap_or(rest.len());
+                types[index..index + len].fill(highlighting::Type::Number);
+                index += len;
+                prev_is_separator = false;
+                continue;
+            }
+
+            if prev_is_separator {
+                if let Some(len) = keyword_len(rest, opts.primary_keywords()) {
+                    types[index..index + len].fill(highlighting::Type::PrimaryKeywords);
+                    index += len;
+                    continue;
+                }
+
+                if let Some(len) = keyword_len(rest, opts.secondary_keywords()) {
+                    types[index..index + len].fill(highlighting::Type::SecondaryKeywords);
+                    index += len;
+                    continue;
+                }
+            }
+
+            prev_is_separator = is_separator(c);
+            index += c.len_utf8();
+        }
+
+        (types, in_comment)
+    }
+
+    fn grapheme_index(&self, byte_index: usize) -> usize {
+        self.string[..byte_index.min(self.string.len())].graphemes(true).count()
     }
 
-    pub fn split(&mut self, at: usize) -> Self {
-        let beginning: String = self.string[..].graphemes(true).take(at).collect();
-        let remainder: String = self.string[..].graphemes(true).skip(at).collect();
-        self.string = beginning;
-        self.update_len();
-        Self::from(&remainder[..])
+    pub fn as_str(&self) -> &str {
+        &self.string
+    }
+
+}
+
+// Tabs run to the next tab stop; everything else takes its Unicode display width,
+// which is 2 for CJK and most emoji and 0 for zero-width characters.
+fn grapheme_width(grapheme: &str, column: usize, tab_width: usize) -> usize {
+    if grapheme == "\t" {
+        tab_width.max(1) - column % tab_width.max(1)
+    } else {
+        grapheme.width()
     }
+}
+
+fn is_separator(c: char) -> bool {
+    c.is_ascii_punctuation() || c.is_whitespace()
+}
+
+// Byte length of a quoted run starting at `text`, honoring backslash escapes.
+fn delimited_len(text: &str, delimiter: char) -> Option<usize> {
+    let mut escaped = false;
 
-    pub fn as_bytes(&self) -> &[u8] {
-        self.string.as_bytes()
+    for (index, c) in text.char_indices().skip(1) {
+        if escaped {
+            escaped = false;
+        } else if c == '\\' {
+            escaped = true;
+        } else if c == delimiter {
+            return Some(index + c.len_utf8());
+        }
     }
 
-}
\ No newline at end of file
+    None
+}
+
+fn keyword_len(text: &str, keywords: &[String]) -> Option<usize> {
+    keywords
+        .iter()
+        .find(|keyword| {
+            text.starts_with(keyword.as_str())
+                && text[keyword.len()..].chars().next().is_none_or(is_separator)
+        })
+        .map(String::len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wrap_points_break_at_the_width() {
+        assert_eq!(Row::from("abcdefg").wrap_points(3, 4), vec![0, 3, 6]);
+        assert_eq!(Row::from("ab").wrap_points(3, 4), vec![0]);
+        assert_eq!(Row::from("").wrap_points(3, 4), vec![0]);
+    }
+
+    #[test]
+    fn a_full_last_line_gets_an_empty_one_after_it() {
+        assert_eq!(Row::from("abcdef").wrap_points(3, 4), vec![0, 3, 6]);
+        assert_eq!(Row::from("abc").wrap_points(3, 4), vec![0, 3]);
+    }
+
+    #[test]
+    fn wide_characters_and_tabs_wrap_by_their_columns() {
+        assert_eq!(Row::from("a漢字").wrap_points(4, 4), vec![0, 2]);
+        assert_eq!(Row::from("a\tb").wrap_points(4, 4), vec![0, 2]);
+    }
+
+    #[test]
+    fn columns_count_display_width() {
+        let row = Row::from("a漢\tb");
+        assert_eq!(row.columns(0, 2, 4), 3);
+        assert_eq!(row.columns(0, 3, 4), 4);
+        assert_eq!(row.columns(0, 4, 4), 5);
+        assert_eq!(row.columns(1, 3, 4), 4);
+        assert_eq!(Row::from("\t").columns(0, 1, 8), 8);
+    }
+
+    #[test]
+    fn index_at_column_finds_the_covering_grapheme() {
+        let row = Row::from("a漢\tb");
+        assert_eq!(row.index_at_column(0, 0, 4), 0);
+        assert_eq!(row.index_at_column(0, 1, 4), 1);
+        assert_eq!(row.index_at_column(0, 2, 4), 1);
+        assert_eq!(row.index_at_column(0, 3, 4), 2);
+        assert_eq!(row.index_at_column(0, 4, 4), 3);
+        assert_eq!(row.index_at_column(0, 9, 4), 4);
+        assert_eq!(row.index_at_column(2, 0, 4), 2);
+    }
+}
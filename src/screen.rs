@@ -0,0 +1,156 @@
+use std::io;
+use std::mem;
+
+use crate::position::Position;
+
+use crossterm::cursor::{Hide, MoveTo, Show};
+use crossterm::queue;
+use crossterm::style::{Attribute, Color, Print, SetAttribute, SetBackgroundColor, SetForegroundColor};
+use crossterm::terminal::{Clear, ClearType};
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+#[derive(Clone, Copy, PartialEq)]
+pub struct Style {
+    pub fg: Color,
+    pub bg: Color,
+    pub reverse: bool,
+}
+
+impl Default for Style {
+    fn default() -> Self {
+        Self {
+            fg: Color::Reset,
+            bg: Color::Reset,
+            reverse: false,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq)]
+pub struct Cell {
+    // Empty for the cells a wide grapheme to the left spills into.
+    pub symbol: String,
+    pub style: Style,
+}
+
+impl Default for Cell {
+    fn default() -> Self {
+        Self::from(" ", Style::default())
+    }
+}
+
+impl Cell {
+    pub fn from(symbol: &str, style: Style) -> Self {
+        Self {
+            symbol: symbol.to_string(),
+            style,
+        }
+    }
+
+    pub fn continuation(style: Style) -> Self {
+        Self::from("", style)
+    }
+}
+
+// What should be on the terminal, alongside what the last frame left there.
+#[derive(Default)]
+pub struct Screen {
+    width: usize,
+    height: usize,
+    cells: Vec<Cell>,
+    previous: Vec<Cell>,
+}
+
+impl Screen {
+    // Starts a blank frame. After a change of size nothing on the terminal can be trusted.
+    pub fn begin(&mut self, width: usize, height: usize) {
+        if (width, height) != (self.width, self.height) {
+            self.width = width;
+            self.height = height;
+            self.previous.clear();
+        }
+        self.cells = vec![Cell::default(); width * height];
+    }
+
+    // Puts `cells` on line `y` from column `x`, dropping whatever runs past the right edge.
+    pub fn put_cells(&mut self, x: usize, y: usize, cells: Vec<Cell>) {
+        if y >= self.height || x >= self.width {
+            return;
+        }
+
+        let line = y * self.width;
+        for (column, cell) in (x..self.width).zip(cells) {
+            self.cells[line + column] = cell;
+        }
+    }
+
+    // Puts `text` on line `y` from column `x` and returns how many columns it took.
+    pub fn put_str(&mut self, x: usize, y: usize, text: &str, style: Style) -> usize {
+        let room = self.width.saturating_sub(x);
+        let mut cells = Vec::new();
+
+        for grapheme in text.graphemes(true) {
+            let width = grapheme.width();
+            if width == 0 {
+                continue;
+            }
+            if cells.len() + width > room {
+                break;
+            }
+
+            cells.push(Cell::from(grapheme, style));
+            for _ in 1..width {
+                cells.push(Cell::continuation(style));
+            }
+        }
+
+        let columns = cells.len();
+        self.put_cells(x, y, cells);
+        columns
+    }
+
+    // Escape sequences that turn the last frame into this one, ending with the cursor at `cursor`.
+    pub fn diff(&mut self, cursor: Option<Position>) -> io::Result<Vec<u8>> {
+        let mut out = Vec::new();
+        queue!(out, Hide)?;
+
+        if self.previous.len() != self.cells.len() {
+            queue!(out, SetAttribute(Attribute::Reset), Clear(ClearType::All))?;
+            self.previous = vec![Cell::default(); self.cells.len()];
+        }
+
+        let mut style = None;
+        let mut next = None;
+        for (index, cell) in self.cells.iter().enumerate() {
+            if *cell == self.previous[index] || cell.symbol.is_empty() {
+                continue;
+            }
+
+            let position = (index % self.width, index / self.width);
+            if next != Some(position) {
+                queue!(out, MoveTo(position.0 as u16, position.1 as u16))?;
+            }
+            if style != Some(cell.style) {
+                queue_style(&mut out, cell.style)?;
+                style = Some(cell.style);
+            }
+
+            queue!(out, Print(&cell.symbol))?;
+            next = Some((position.0 + cell.symbol.width().max(1), position.1));
+        }
+
+        queue!(out, SetAttribute(Attribute::Reset), SetForegroundColor(Color::Reset), SetBackgroundColor(Color::Reset))?;
+        if let Some(Position { x, y }) = cursor {
+            queue!(out, MoveTo(x as u16, y as u16), Show)?;
+        }
+
+        self.previous = mem::take(&mut self.cells);
+        Ok(out)
+    }
+}
+
+fn queue_style(out: &mut Vec<u8>, style: Style) -> io::Result<()> {
+    let reverse = if style.reverse { Attribute::Reverse } else { Attribute::NoReverse };
+    queue!(out, SetForegroundColor(style.fg), SetBackgroundColor(style.bg), SetAttribute(reverse))
+}
@@ -0,0 +1,129 @@
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::process;
+
+// The first line of a swap file names the session that writes it.
+const HEADER: &str = "rhelico swap";
+
+// `dir/name` is journaled to `dir/.name.swp` while it has unsaved changes.
+pub fn path_for(file_name: &str) -> PathBuf {
+    let path = Path::new(file_name);
+    let name = path
+        .file_name()
+        .map_or_else(String::new, |name| name.to_string_lossy().into_owned());
+
+    path.with_file_name(format!(".{}.swp", name))
+}
+
+#[derive(PartialEq)]
+pub struct Owner {
+    pub pid: u32,
+    pub host: String,
+}
+
+impl Owner {
+    pub fn current() -> Self {
+        Self { pid: process::id(), host: hostname() }
+    }
+
+    pub fn header(&self) -> String {
+        format!("{} {} {}\n", HEADER, self.pid, self.host)
+    }
+
+    // Whether the session that wrote the swap file may still be running. One on another
+    // machine can't be checked, so it counts as running.
+    pub fn is_alive(&self) -> bool {
+        self.host != hostname() || is_running(self.pid)
+    }
+}
+
+impl fmt::Display for Owner {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "process {} on {}", self.pid, self.host)
+    }
+}
+
+// Splits a swap file into the session that wrote it and the journaled text. Files without
+// the header are all text.
+pub fn parse(contents: &str) -> (Option<Owner>, &str) {
+    let Some((first, text)) = contents.split_once('\n') else {
+        return (None, contents);
+    };
+    match owner_in(first) {
+        Some(owner) => (Some(owner), text),
+        None => (None, contents),
+    }
+}
+
+// Who wrote the swap file at `path`, reading no further than its first line.
+pub fn owner(path: &Path) -> Option<Owner> {
+    let mut first = String::new();
+    BufReader::new(fs::File::open(path).ok()?).read_line(&mut first).ok()?;
+    owner_in(first.trim_end_matches('\n'))
+}
+
+fn owner_in(line: &str) -> Option<Owner> {
+    let mut words = line.strip_prefix(HEADER)?.split_whitespace();
+    let pid = words.next()?.parse::<u32>().ok()?;
+    let host = words.next()?.to_string();
+    Some(Owner { pid, host })
+}
+
+fn hostname() -> String {
+    ["/proc/sys/kernel/hostname", "/etc/hostname"]
+        .iter()
+        .find_map(|path| fs::read_to_string(path).ok())
+        .or_else(|| env::var("HOSTNAME").ok())
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty() && !name.contains(char::is_whitespace))
+        .unwrap_or_else(|| "localhost".to_string())
+}
+
+#[cfg(unix)]
+fn is_running(pid: u32) -> bool {
+    let proc = Path::new("/proc");
+    if proc.is_dir() {
+        return proc.join(pid.to_string()).exists();
+    }
+    process::Command::new("kill")
+        .args(["-0", &pid.to_string()])
+        .stderr(process::Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success())
+}
+
+// Without a way to look, assume the other session is still there.
+#[cfg(not(unix))]
+fn is_running(_pid: u32) -> bool {
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn header_round_trips() {
+        let owner = Owner { pid: 42, host: "box".to_string() };
+        let contents = format!("{}one\ntwo\n", owner.header());
+        let (parsed, text) = parse(&contents);
+        assert!(parsed == Some(owner));
+        assert_eq!(text, "one\ntwo\n");
+    }
+
+    #[test]
+    fn files_without_a_header_are_all_text() {
+        assert!(parse("one\ntwo\n") == (None, "one\ntwo\n"));
+        assert!(parse("rhelico swap x\ntwo").0.is_none());
+        assert!(parse("").0.is_none());
+    }
+
+    #[test]
+    fn this_session_is_alive() {
+        assert!(Owner::current().is_alive());
+        assert!(Owner { pid: 42, host: "some-other-machine".to_string() }.is_alive());
+    }
+}
@@ -1,12 +1,13 @@
-use std::io;
 use std::io::{stdout, Write};
-use crossterm::{ExecutableCommand, execute};
-use crossterm::style::{Stylize, SetBackgroundColor, SetForegroundColor, ResetColor, Color};
-use crossterm::cursor::{MoveTo, Show, Hide};
-use crossterm::terminal::{size, ClearType, Clear};
-use crossterm::event::{Event, read };
+use std::time::Duration;
+use crossterm::execute;
+use crossterm::cursor::MoveTo;
+use crossterm::terminal::size;
+use crossterm::event::{Event, poll, read, EnableBracketedPaste, DisableBracketedPaste, EnableMouseCapture, DisableMouseCapture};
 use crossterm::terminal::{EnterAlternateScreen, LeaveAlternateScreen, enable_raw_mode, disable_raw_mode};
-use crate::Position;
+
+const MIN_ROWS: u16 = 3;
+const MIN_COLUMNS: u16 = 10;
 
 pub struct Size {
     pub columns: u16,
@@ -24,6 +25,8 @@ impl Terminal {
 
         enable_raw_mode().expect("Unable to enter raw mode!");
         execute!(stdout(), EnterAlternateScreen, MoveTo(0, 0)).expect("Failed to enter Alternate screen mode.");
+        // Pasted text arrives as one Event::Paste instead of a key press per character.
+        execute!(stdout(), EnableBracketedPaste).ok();
 
 
 
@@ -31,67 +34,54 @@ impl Terminal {
         Ok(Self {
             size: Size {
                 columns: sz.0,
-                rows: sz.1.saturating_sub(2),
+                rows: sz.1,
             },
         })
     }
 
     pub fn shutdown(&self) {
         disable_raw_mode().ok();
+        execute!(stdout(), DisableBracketedPaste, DisableMouseCapture).ok();
         execute!(stdout(), LeaveAlternateScreen).expect("Issue leaving alternate screen mode.");
 
     }
 
-    pub fn size(&self) -> &Size {
-        &self.size
-    }
-
-    pub fn read_key(&self) -> Result<Event, std::io::Error>  {
-        read()
-    }
-
-    pub fn clear_screen(&self) {
-        execute!(stdout(), Clear(ClearType::Purge)).ok();
-    }
-
-    pub fn clear_current_line(&self) {
-        execute!(stdout(), Clear(ClearType::CurrentLine)).ok();
+    // While the mouse is captured the terminal's own text selection stops working.
+    pub fn capture_mouse(&self, capture: bool) {
+        if capture {
+            execute!(stdout(), EnableMouseCapture).ok();
+        } else {
+            execute!(stdout(), DisableMouseCapture).ok();
+        }
     }
 
-    pub fn set_bg_color(&self, color: Color) {
-        stdout().execute(SetBackgroundColor(color)).expect("Failed changing background color");
-    }
-
-    pub fn reset_bg_color(&self) {
-        stdout().execute(SetBackgroundColor(Color::Reset)).expect("Unable to reset bg color!");
-    }
-
-    pub fn set_fg_color(&self, color: Color) {
-        stdout().execute(SetForegroundColor(color)).expect("Failed to set FG color");
-    }
-
-    pub fn reset_fg_color(&self) {
-        stdout().execute(SetForegroundColor(Color::Reset)).expect("Failed to reset FG color");
+    pub fn size(&self) -> &Size {
+        &self.size
     }
 
-     #[allow(clippy::cast_possible_truncation)]
-    pub fn cursor_position(&self, position: &Position) {
-         let Position{x, y} = position;
-         let x = *x as u16;
-         let y = *y as u16;
-
-        execute!(stdout(), MoveTo(x, y)).expect("Failed to move cursor");
+    // Keeps `size` current as resize events go by, whoever ends up handling them.
+    pub fn read_key(&mut self) -> Result<Event, std::io::Error>  {
+        let event = read()?;
+        if let Event::Resize(columns, rows) = event {
+            self.size = Size { columns, rows };
+        }
+        Ok(event)
     }
 
-    pub fn flush(&self) {
-        stdout().flush().expect("Failed to flush to stdout!");
+    // Whether there is room for at least one line of text above the status and message bars.
+    pub fn is_too_small(&self) -> bool {
+        self.size.rows < MIN_ROWS || self.size.columns < MIN_COLUMNS
     }
 
-    pub fn cursor_hide(&self) {
-        execute!(stdout(), Hide).expect("Failed to hide cursor");
+    // Waits up to `timeout` for input, so the caller can do housekeeping while idle.
+    pub fn has_event(&self, timeout: Duration) -> Result<bool, std::io::Error> {
+        poll(timeout)
     }
 
-    pub fn cursor_show(&self) {
-        execute!(stdout(), Show).expect("Failed to show cursor.");
+    // Sends a whole frame in one write, so it never shows half drawn.
+    pub fn write(&self, frame: &[u8]) {
+        let mut out = stdout().lock();
+        out.write_all(frame).ok();
+        out.flush().ok();
     }
 }
@@ -0,0 +1,643 @@
+use crate::document::Document;
+use crate::position::Position;
+
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use unicode_segmentation::UnicodeSegmentation;
+
+// Counts above this are taken as this, so a stray run of digits can't paste or repeat an
+// insert until memory or patience runs out.
+const MAX_COUNT: usize = 9999;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Mode {
+    Normal,
+    Insert,
+    Visual,
+}
+
+impl Mode {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Normal => "NORMAL",
+            Self::Insert => "INSERT",
+            Self::Visual => "VISUAL",
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Operator {
+    Delete,
+    Change,
+    Yank,
+    Indent,
+    Outdent,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Motion {
+    Left,
+    Right,
+    Up,
+    Down,
+    WordForward,
+    WordBackward,
+    WordEnd,
+    LineStart,
+    FirstNonBlank,
+    LineEnd,
+    FirstLine,
+    LastLine,
+    // f, t, F and T: to, or up to, the next or previous `c` on the line.
+    Find { c: char, forward: bool, till: bool },
+    // The doubled operator of dd, yy, >> and the like: the cursor's line and the ones below.
+    Lines,
+}
+
+impl Motion {
+    // Operators on these take whole lines.
+    pub fn linewise(self) -> bool {
+        matches!(self, Self::Up | Self::Down | Self::FirstLine | Self::LastLine | Self::Lines)
+    }
+
+    // Operators on these take the grapheme the motion lands on as well.
+    pub fn inclusive(self) -> bool {
+        matches!(self, Self::WordEnd | Self::LineEnd | Self::Find { forward: true, .. })
+    }
+}
+
+// Where Insert mode starts for i, a, I, A, o and O.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Entry {
+    Before,
+    After,
+    LineStart,
+    LineEnd,
+    LineBelow,
+    LineAbove,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Action {
+    Move(Motion),
+    Operate(Operator, Motion),
+    // An operator on what Visual mode has selected.
+    OperateSelection(Operator),
+    Insert(Entry),
+    Paste { before: bool },
+    Undo,
+    Redo,
+    Visual,
+    Repeat,
+    Escape,
+    CommandLine,
+}
+
+impl Action {
+    // Whether `.` can do this again.
+    pub fn is_change(self) -> bool {
+        match self {
+            Self::Operate(operator, _) => operator != Operator::Yank,
+            Self::Insert(_) | Self::Paste { .. } => true,
+            _ => false,
+        }
+    }
+}
+
+pub enum Parse {
+    Complete(Option<usize>, Action),
+    Incomplete,
+    Invalid,
+}
+
+// The last change, with whatever was typed in the Insert mode it led to.
+#[derive(Clone)]
+pub struct Change {
+    pub count: Option<usize>,
+    pub action: Action,
+    pub typed: Vec<KeyEvent>,
+}
+
+pub struct Vi {
+    pub mode: Mode,
+    // Keys of a command typed so far.
+    pending: Vec<char>,
+    pub last_change: Option<Change>,
+    // The change that opened the current Insert mode, collecting the keys typed since.
+    pub recording: Option<Change>,
+}
+
+impl Default for Vi {
+    fn default() -> Self {
+        Self {
+            mode: Mode::Normal,
+            pending: Vec::new(),
+            last_change: None,
+            recording: None,
+        }
+    }
+}
+
+impl Vi {
+    // Adds a key to the command being typed, forgetting it once it is complete or makes no sense.
+    pub fn key(&mut self, c: char) -> Parse {
+        self.pending.push(c);
+        let parsed = parse(&self.pending, self.mode);
+        if !matches!(parsed, Parse::Incomplete) {
+            self.pending.clear();
+        }
+        parsed
+    }
+
+    pub fn cancel(&mut self) {
+        self.pending.clear();
+    }
+
+    pub fn record(&mut self, key: KeyEvent) {
+        if let Some(change) = &mut self.recording {
+            change.typed.push(key);
+        }
+    }
+
+    // The mode and any half typed command, for the status bar.
+    pub fn status(&self) -> String {
+        if self.pending.is_empty() {
+            self.mode.name().to_string()
+        } else {
+            format!("{} {}", self.mode.name(), self.pending.iter().collect::<String>())
+        }
+    }
+}
+
+// The command keys a key stands for outside Insert mode: arrows and the like act as the
+// motions they match.
+pub fn key_char(event: KeyEvent) -> Option<char> {
+    if event.modifiers.intersects(KeyModifiers::CONTROL | KeyModifiers::ALT) {
+        return None;
+    }
+
+    match event.code {
+        KeyCode::Char(c) => Some(c),
+        KeyCode::Left | KeyCode::Backspace => Some('h'),
+        KeyCode::Right => Some('l'),
+        KeyCode::Up => Some('k'),
+        KeyCode::Down | KeyCode::Enter => Some('j'),
+        KeyCode::Home => Some('0'),
+        KeyCode::End => Some('$'),
+        KeyCode::Delete => Some('x'),
+        _ => None,
+    }
+}
+
+// [count] motion, [count] operator [count] motion, a doubled operator, or a single key action.
+fn parse(keys: &[char], mode: Mode) -> Parse {
+    let (count, keys) = count(keys);
+    if let (Mode::Normal, [key, rest @ ..]) = (mode, keys) {
+        if let Some(operator) = operator(*key) {
+            return operate(count, operator, *key, rest);
+        }
+    }
+
+    let action = match (mode, keys) {
+        (_, []) => return Parse::Incomplete,
+        (Mode::Visual, ['v']) => Action::Escape,
+        (Mode::Visual, ['d' | 'x']) => Action::OperateSelection(Operator::Delete),
+        (Mode::Visual, ['c' | 's']) => Action::OperateSelection(Operator::Change),
+        (Mode::Visual, ['y']) => Action::OperateSelection(Operator::Yank),
+        (Mode::Visual, ['>']) => Action::OperateSelection(Operator::Indent),
+        (Mode::Visual, ['<']) => Action::OperateSelection(Operator::Outdent),
+        (Mode::Normal, ['i']) => Action::Insert(Entry::Before),
+        (Mode::Normal, ['a']) => Action::Insert(Entry::After),
+        (Mode::Normal, ['I']) => Action::Insert(Entry::LineStart),
+        (Mode::Normal, ['A']) => Action::Insert(Entry::LineEnd),
+        (Mode::Normal, ['o']) => Action::Insert(Entry::LineBelow),
+        (Mode::Normal, ['O']) => Action::Insert(Entry::LineAbove),
+        (Mode::Normal, ['x']) => Action::Operate(Operator::Delete, Motion::Right),
+        (Mode::Normal, ['X']) => Action::Operate(Operator::Delete, Motion::Left),
+        (Mode::Normal, ['D']) => Action::Operate(Operator::Delete, Motion::LineEnd),
+        (Mode::Normal, ['C']) => Action::Operate(Operator::Change, Motion::LineEnd),
+        (Mode::Normal, ['s']) => Action::Operate(Operator::Change, Motion::Right),
+        (Mode::Normal, ['S']) => Action::Operate(Operator::Change, Motion::Lines),
+        (Mode::Normal, ['Y']) => Action::Operate(Operator::Yank, Motion::Lines),
+        (Mode::Normal, ['p']) => Action::Paste { before: false },
+        (Mode::Normal, ['P']) => Action::Paste { before: true },
+        (Mode::Normal, ['u']) => Action::Undo,
+        (Mode::Normal, ['v']) => Action::Visual,
+        (Mode::Normal, ['.']) => Action::Repeat,
+        (Mode::Normal, [':']) => Action::CommandLine,
+        (_, keys) => match motion(keys) {
+            Some(Ok(motion)) => Action::Move(motion),
+            Some(Err(())) => return Parse::Invalid,
+            None => return Parse::Incomplete,
+        },
+    };
+
+    Parse::Complete(count, action)
+}
+
+// The motion after an operator, which may carry its own count.
+fn operate(count: Option<usize>, operator: Operator, key: char, keys: &[char]) -> Parse {
+    let (motion_count, keys) = self::count(keys);
+    let motion = match keys {
+        [] => return Parse::Incomplete,
+        [doubled] if *doubled == key => Motion::Lines,
+        keys => match motion(keys) {
+            Some(Ok(motion)) => motion,
+            Some(Err(())) => return Parse::Invalid,
+            None => return Parse::Incomplete,
+        },
+    };
+
+    // 2d3w deletes six words.
+    let count = match (count, motion_count) {
+        (Some(count), Some(motion_count)) => Some(count.saturating_mul(motion_count).min(MAX_COUNT)),
+        (count, motion_count) => count.or(motion_count),
+    };
+    Parse::Complete(count, Action::Operate(operator, motion))
+}
+
+// Leading digits, except that a lone 0 is the motion to the start of the line.
+fn count(keys: &[char]) -> (Option<usize>, &[char]) {
+    if keys.first() == Some(&'0') {
+        return (None, keys);
+    }
+
+    let digits = keys.iter().take_while(|c| c.is_ascii_digit()).count();
+    // Too many digits to parse is a count above the limit too.
+    let count = (digits > 0).then(|| {
+        keys[..digits].iter().collect::<String>().parse::<usize>().map_or(MAX_COUNT, |count| count.min(MAX_COUNT))
+    });
+    (count, &keys[digits..])
+}
+
+fn operator(key: char) -> Option<Operator> {
+    match key {
+        'd' => Some(Operator::Delete),
+        'c' => Some(Operator::Change),
+        'y' => Some(Operator::Yank),
+        '>' => Some(Operator::Indent),
+        '<' => Some(Operator::Outdent),
+        _ => None,
+    }
+}
+
+// None while the keys could still become a motion.
+fn motion(keys: &[char]) -> Option<Result<Motion, ()>> {
+    let motion = match keys {
+        ['h'] => Motion::Left,
+        ['l' | ' '] => Motion::Right,
+        ['j'] => Motion::Down,
+        ['k'] => Motion::Up,
+        ['w'] => Motion::WordForward,
+        ['b'] => Motion::WordBackward,
+        ['e'] => Motion::WordEnd,
+        ['0'] => Motion::LineStart,
+        ['^'] => Motion::FirstNonBlank,
+        ['$'] => Motion::LineEnd,
+        ['G'] => Motion::LastLine,
+        ['g', 'g'] => Motion::FirstLine,
+        ['g' | 'f' | 't' | 'F' | 'T'] => return None,
+        [kind @ ('f' | 't' | 'F' | 'T'), c] => Motion::Find {
+            c: *c,
+            forward: kind.is_lowercase(),
+            till: matches!(kind, 't' | 'T'),
+        },
+        _ => return Some(Err(())),
+    };
+    Some(Ok(motion))
+}
+
+// Where `motion` repeated `count` times takes the cursor from `at`, or None if it can't move.
+// G and gg take the count as a line number instead.
+pub fn target(document: &Document, at: Position, motion: Motion, count: Option<usize>) -> Option<Position> {
+    let times = count.unwrap_or(1).max(1);
+    let last_line = document.len().saturating_sub(1);
+    let line = graphemes(document, at.y);
+
+    let target = match motion {
+        Motion::Left if at.x == 0 => return None,
+        Motion::Left => Position { x: at.x.saturating_sub(times), y: at.y },
+        Motion::Right => Position { x: at.x.saturating_add(times).min(line.len()), y: at.y },
+        Motion::Up if at.y == 0 => return None,
+        Motion::Up => vertical(document, at, at.y.saturating_sub(times)),
+        Motion::Down if at.y >= last_line => return None,
+        Motion::Down => vertical(document, at, at.y.saturating_add(times).min(last_line)),
+        Motion::WordForward => word_forward(document, at, times),
+        Motion::WordBackward => word_backward(document, at, times),
+        Motion::WordEnd => word_end(document, at, times),
+        Motion::LineStart => Position { x: 0, y: at.y },
+        Motion::FirstNonBlank => Position { x: first_non_blank(document, at.y), y: at.y },
+        Motion::LineEnd => {
+            let y = at.y.saturating_add(times - 1).min(last_line);
+            Position { x: graphemes(document, y).len().saturating_sub(1), y }
+        },
+        Motion::FirstLine | Motion::LastLine => {
+            let y = match (count, motion) {
+                (Some(count), _) => count.saturating_sub(1).min(last_line),
+                (None, Motion::FirstLine) => 0,
+                (None, _) => last_line,
+            };
+            Position { x: first_non_blank(document, y), y }
+        },
+        Motion::Find { c, forward, till } => {
+            let c = c.to_string();
+            let found = line.iter().enumerate().filter(|(_, grapheme)| **grapheme == c).map(|(index, _)| index);
+            // t stops next to the target, so a target right beside the cursor leaves it in place.
+            let x = if forward {
+                found.filter(|index| *index > at.x).nth(times - 1)? - usize::from(till)
+            } else {
+                let before: Vec<usize> = found.filter(|index| *index < at.x).collect();
+                before.iter().rev().nth(times - 1)? + usize::from(till)
+            };
+            Position { x, y: at.y }
+        },
+        Motion::Lines => Position { x: at.x, y: at.y.saturating_add(times - 1).min(last_line) },
+    };
+
+    Some(target)
+}
+
+// The first grapheme of row `y` that is not blank, or its end if it is all blank.
+pub fn first_non_blank(document: &Document, y: usize) -> usize {
+    let line = graphemes(document, y);
+    line.iter().position(|grapheme| class(grapheme) != Class::Blank).unwrap_or(line.len())
+}
+
+pub fn is_blank(document: &Document, at: Position) -> bool {
+    graphemes(document, at.y).get(at.x).is_none_or(|grapheme| class(grapheme) == Class::Blank)
+}
+
+fn graphemes(document: &Document, y: usize) -> Vec<String> {
+    document
+        .row(y)
+        .map_or_else(Vec::new, |row| row.as_str().graphemes(true).map(String::from).collect())
+}
+
+fn vertical(document: &Document, at: Position, y: usize) -> Position {
+    Position { x: at.x.min(graphemes(document, y).len()), y }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Class {
+    // Line ends count as blanks too.
+    Blank,
+    Word,
+    Punctuation,
+}
+
+fn class(grapheme: &str) -> Class {
+    match grapheme.chars().next() {
+        Some(c) if c.is_whitespace() => Class::Blank,
+        Some(c) if c.is_alphanumeric() || c == '_' => Class::Word,
+        Some(_) => Class::Punctuation,
+        None => Class::Blank,
+    }
+}
+
+// Steps through the document a grapheme at a time, with a stop at the end of every line.
+struct Walker<'a> {
+    document: &'a Document,
+    at: Position,
+    line: Vec<String>,
+}
+
+impl<'a> Walker<'a> {
+    fn new(document: &'a Document, at: Position) -> Self {
+        Self { document, at, line: graphemes(document, at.y) }
+    }
+
+    fn class(&self) -> Class {
+        self.line.get(self.at.x).map_or(Class::Blank, |grapheme| class(grapheme))
+    }
+
+    fn class_at(&self, x: usize) -> Class {
+        self.line.get(x).map_or(Class::Blank, |grapheme| class(grapheme))
+    }
+
+    fn forward(&mut self) -> bool {
+        if self.at.x < self.line.len() {
+            self.at.x += 1;
+        } else if self.at.y + 1 < self.document.len() {
+            self.at = Position { x: 0, y: self.at.y + 1 };
+            self.line = graphemes(self.document, self.at.y);
+        } else {
+            return false;
+        }
+        true
+    }
+
+    fn backward(&mut self) -> bool {
+        if self.at.x > 0 {
+            self.at.x -= 1;
+        } else if self.at.y > 0 {
+            self.line = graphemes(self.document, self.at.y - 1);
+            self.at = Position { x: self.line.len(), y: self.at.y - 1 };
+        } else {
+            return false;
+        }
+        true
+    }
+}
+
+// w: the start of the next word, with empty lines counting as words.
+fn word_forward(document: &Document, at: Position, times: usize) -> Position {
+    let mut walker = Walker::new(document, at);
+    for _ in 0..times {
+        let start = walker.class();
+        if start != Class::Blank {
+            while walker.class() == start {
+                if !walker.forward() {
+                    return walker.at;
+                }
+            }
+        }
+
+        while walker.class() == Class::Blank {
+            if !walker.forward() || walker.line.is_empty() {
+                break;
+            }
+        }
+    }
+    walker.at
+}
+
+// b: the start of this word, or of the one before when already there.
+fn word_backward(document: &Document, at: Position, times: usize) -> Position {
+    let mut walker = Walker::new(document, at);
+    for _ in 0..times {
+        if !walker.backward() {
+            break;
+        }
+        while walker.class() == Class::Blank && !walker.line.is_empty() {
+            if !walker.backward() {
+                break;
+            }
+        }
+
+        let start = walker.class();
+        if start != Class::Blank {
+            while walker.at.x > 0 && walker.class_at(walker.at.x - 1) == start {
+                walker.at.x -= 1;
+            }
+        }
+    }
+    walker.at
+}
+
+// e: the end of this word, or of the next one when already there.
+fn word_end(document: &Document, at: Position, times: usize) -> Position {
+    let mut walker = Walker::new(document, at);
+    for _ in 0..times {
+        if !walker.forward() {
+            break;
+        }
+        while walker.class() == Class::Blank {
+            if !walker.forward() {
+                break;
+            }
+        }
+
+        let start = walker.class();
+        while walker.at.x + 1 < walker.line.len() && walker.class_at(walker.at.x + 1) == start {
+            walker.at.x += 1;
+        }
+    }
+    walker.at
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parsed(keys: &str, mode: Mode) -> Parse {
+        parse(&keys.chars().collect::<Vec<char>>(), mode)
+    }
+
+    fn complete(keys: &str) -> Option<(Option<usize>, Action)> {
+        match parsed(keys, Mode::Normal) {
+            Parse::Complete(count, action) => Some((count, action)),
+            _ => None,
+        }
+    }
+
+    fn lands(text: &str, at: (usize, usize), motion: Motion, count: Option<usize>) -> Option<(usize, usize)> {
+        let document = Document::from_text(text);
+        target(&document, Position { x: at.0, y: at.1 }, motion, count).map(|position| (position.x, position.y))
+    }
+
+    fn find(c: char, forward: bool, till: bool) -> Motion {
+        Motion::Find { c, forward, till }
+    }
+
+    #[test]
+    fn counts_apply_to_motions() {
+        assert!(complete("w") == Some((None, Action::Move(Motion::WordForward))));
+        assert!(complete("3w") == Some((Some(3), Action::Move(Motion::WordForward))));
+        assert!(complete("12j") == Some((Some(12), Action::Move(Motion::Down))));
+        assert!(complete("0") == Some((None, Action::Move(Motion::LineStart))));
+        assert!(complete("10G") == Some((Some(10), Action::Move(Motion::LastLine))));
+    }
+
+    #[test]
+    fn counts_are_capped() {
+        assert!(complete("99999999999999999999999p") == Some((Some(MAX_COUNT), Action::Paste { before: false })));
+        assert!(complete("99999i") == Some((Some(MAX_COUNT), Action::Insert(Entry::Before))));
+        assert!(complete("5000d5000w") == Some((Some(MAX_COUNT), Action::Operate(Operator::Delete, Motion::WordForward))));
+    }
+
+    #[test]
+    fn operators_wait_for_a_motion() {
+        assert!(matches!(parsed("d", Mode::Normal), Parse::Incomplete));
+        assert!(matches!(parsed("d2", Mode::Normal), Parse::Incomplete));
+        assert!(complete("dw") == Some((None, Action::Operate(Operator::Delete, Motion::WordForward))));
+        assert!(complete("c$") == Some((None, Action::Operate(Operator::Change, Motion::LineEnd))));
+        assert!(complete("y0") == Some((None, Action::Operate(Operator::Yank, Motion::LineStart))));
+    }
+
+    #[test]
+    fn operator_and_motion_counts_multiply() {
+        assert!(complete("2d3w") == Some((Some(6), Action::Operate(Operator::Delete, Motion::WordForward))));
+        assert!(complete("d3w") == Some((Some(3), Action::Operate(Operator::Delete, Motion::WordForward))));
+        assert!(complete("2dw") == Some((Some(2), Action::Operate(Operator::Delete, Motion::WordForward))));
+    }
+
+    #[test]
+    fn doubled_operators_take_lines() {
+        assert!(complete("dd") == Some((None, Action::Operate(Operator::Delete, Motion::Lines))));
+        assert!(complete("3yy") == Some((Some(3), Action::Operate(Operator::Yank, Motion::Lines))));
+        assert!(complete(">>") == Some((None, Action::Operate(Operator::Indent, Motion::Lines))));
+        assert!(complete("d>").is_none());
+    }
+
+    #[test]
+    fn two_key_motions() {
+        assert!(matches!(parsed("g", Mode::Normal), Parse::Incomplete));
+        assert!(complete("gg") == Some((None, Action::Move(Motion::FirstLine))));
+        assert!(matches!(parsed("f", Mode::Normal), Parse::Incomplete));
+        assert!(complete("fx") == Some((None, Action::Move(find('x', true, false)))));
+        assert!(complete("2Tx") == Some((Some(2), Action::Move(find('x', false, true)))));
+        assert!(complete("dt)") == Some((None, Action::Operate(Operator::Delete, find(')', true, true)))));
+    }
+
+    #[test]
+    fn unknown_keys_are_invalid() {
+        assert!(matches!(parsed("Z", Mode::Normal), Parse::Invalid));
+        assert!(matches!(parsed("gz", Mode::Normal), Parse::Invalid));
+        assert!(matches!(parsed("dZ", Mode::Normal), Parse::Invalid));
+    }
+
+    #[test]
+    fn visual_mode_operates_on_the_selection() {
+        assert!(matches!(parsed("d", Mode::Visual), Parse::Complete(None, Action::OperateSelection(Operator::Delete))));
+        assert!(matches!(parsed("y", Mode::Visual), Parse::Complete(None, Action::OperateSelection(Operator::Yank))));
+        assert!(matches!(parsed("3w", Mode::Visual), Parse::Complete(Some(3), Action::Move(Motion::WordForward))));
+        assert!(matches!(parsed("i", Mode::Visual), Parse::Invalid));
+    }
+
+    #[test]
+    fn find_moves_onto_the_character() {
+        let text = "a,b,c,d";
+        assert!(lands(text, (0, 0), find(',', true, false), None) == Some((1, 0)));
+        assert!(lands(text, (0, 0), find(',', true, false), Some(2)) == Some((3, 0)));
+        assert!(lands(text, (6, 0), find(',', false, false), None) == Some((5, 0)));
+        assert!(lands(text, (6, 0), find(',', false, false), Some(3)) == Some((1, 0)));
+        assert!(lands(text, (0, 0), find('x', true, false), None).is_none());
+        assert!(lands(text, (0, 0), find(',', true, false), Some(4)).is_none());
+    }
+
+    #[test]
+    fn till_stops_next_to_the_character() {
+        let text = "a,b,c,d";
+        assert!(lands(text, (0, 0), find('c', true, true), None) == Some((3, 0)));
+        assert!(lands(text, (6, 0), find('b', false, true), None) == Some((3, 0)));
+        assert!(lands(text, (0, 0), find(',', true, true), Some(2)) == Some((2, 0)));
+    }
+
+    #[test]
+    fn till_an_adjacent_character_stays_put() {
+        let text = "a,b,c,d";
+        assert!(lands(text, (0, 0), find(',', true, true), None) == Some((0, 0)));
+        assert!(lands(text, (2, 0), find(',', false, true), None) == Some((2, 0)));
+    }
+
+    #[test]
+    fn word_motions() {
+        let text = "foo.bar baz\n\nqux";
+        assert!(lands(text, (0, 0), Motion::WordForward, None) == Some((3, 0)));
+        assert!(lands(text, (0, 0), Motion::WordForward, Some(3)) == Some((8, 0)));
+        assert!(lands(text, (8, 0), Motion::WordForward, None) == Some((0, 1)));
+        assert!(lands(text, (8, 0), Motion::WordBackward, None) == Some((4, 0)));
+        assert!(lands(text, (0, 0), Motion::WordEnd, None) == Some((2, 0)));
+    }
+
+    #[test]
+    fn line_motions() {
+        let text = "  one\ntwo\n  three";
+        assert!(lands(text, (4, 0), Motion::FirstNonBlank, None) == Some((2, 0)));
+        assert!(lands(text, (0, 0), Motion::LineEnd, None) == Some((4, 0)));
+        assert!(lands(text, (0, 0), Motion::LastLine, None) == Some((2, 2)));
+        assert!(lands(text, (0, 2), Motion::FirstLine, None) == Some((2, 0)));
+        assert!(lands(text, (0, 0), Motion::LastLine, Some(2)) == Some((0, 1)));
+        assert!(lands(text, (0, 0), Motion::Up, None).is_none());
+    }
+}
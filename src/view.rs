@@ -0,0 +1,139 @@
+use crate::position::Position;
+
+// A window onto one of the editor's buffers.
+#[derive(Default, Clone, Copy)]
+pub struct View {
+    pub buffer: usize,
+    pub cursor_position: Position,
+    pub offset: Position,
+    // The other end of the selection, set while Shift is held during movement.
+    pub anchor: Option<Position>,
+}
+
+impl View {
+    // The selected span in document order, or None when nothing is selected.
+    pub fn selection(&self) -> Option<(Position, Position)> {
+        let anchor = self.anchor?;
+        let cursor = self.cursor_position;
+        if anchor == cursor {
+            None
+        } else if (anchor.y, anchor.x) < (cursor.y, cursor.x) {
+            Some((anchor, cursor))
+        } else {
+            Some((cursor, anchor))
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct Rect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum SplitDirection {
+    // One view above the other.
+    Horizontal,
+    // Side by side, with a one column separator between them.
+    Vertical,
+}
+
+pub enum Layout {
+    View(usize),
+    Split {
+        direction: SplitDirection,
+        first: Box<Layout>,
+        second: Box<Layout>,
+    },
+}
+
+impl Layout {
+    // Replaces `view` with a split holding it and `new_view`.
+    pub fn split(&mut self, view: usize, new_view: usize, direction: SplitDirection) -> bool {
+        match self {
+            Layout::View(id) if *id == view => {
+                *self = Layout::Split {
+                    direction,
+                    first: Box::new(Layout::View(view)),
+                    second: Box::new(Layout::View(new_view)),
+                };
+                true
+            },
+            Layout::View(_) => false,
+            Layout::Split { first, second, .. } => {
+                first.split(view, new_view, direction) || second.split(view, new_view, direction)
+            },
+        }
+    }
+
+    // Drops `view`, letting its sibling take over the space, and renumbers the views after it.
+    pub fn remove(&mut self, view: usize) -> bool {
+        let removed = self.remove_leaf(view);
+        if removed {
+            self.renumber(view);
+        }
+        removed
+    }
+
+    fn remove_leaf(&mut self, view: usize) -> bool {
+        let Layout::Split { first, second, .. } = self else {
+            return false;
+        };
+
+        let sibling = if matches!(**first, Layout::View(id) if id == view) {
+            mem_take(second)
+        } else if matches!(**second, Layout::View(id) if id == view) {
+            mem_take(first)
+        } else {
+            return first.remove_leaf(view) || second.remove_leaf(view);
+        };
+
+        *self = sibling;
+        true
+    }
+
+    fn renumber(&mut self, removed: usize) {
+        match self {
+            Layout::View(id) => {
+                if *id > removed {
+                    *id -= 1;
+                }
+            },
+            Layout::Split { first, second, .. } => {
+                first.renumber(removed);
+                second.renumber(removed);
+            },
+        }
+    }
+
+    // Screen areas of every view, in drawing order. Each area includes the view's status bar.
+    pub fn rects(&self, area: Rect, rects: &mut Vec<(usize, Rect)>) {
+        match self {
+            Layout::View(id) => rects.push((*id, area)),
+            Layout::Split { direction: SplitDirection::Horizontal, first, second } => {
+                let top = area.height / 2;
+                first.rects(Rect { height: top, ..area }, rects);
+                second.rects(Rect { y: area.y + top, height: area.height - top, ..area }, rects);
+            },
+            Layout::Split { direction: SplitDirection::Vertical, first, second } => {
+                let left = area.width.saturating_sub(1) / 2;
+                first.rects(Rect { width: left, ..area }, rects);
+                second.rects(
+                    Rect {
+                        x: area.x + left + 1,
+                        width: area.width.saturating_sub(left + 1),
+                        ..area
+                    },
+                    rects,
+                );
+            },
+        }
+    }
+}
+
+fn mem_take(layout: &mut Layout) -> Layout {
+    std::mem::replace(layout, Layout::View(0))
+}